@@ -8,12 +8,16 @@ use std::fs::File;
 
 use std::env;
 use std::io::BufReader;
+use std::io::IsTerminal;
 use std::io::Read;
 
 use lazy_static::lazy_static;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use regex::{Regex, RegexBuilder};
 
@@ -22,32 +26,47 @@ use walkdir::WalkDir;
 
 use cfgmap::{CfgMap, CfgValue, Checkable, Condition::*};
 use data_encoding::HEXLOWER;
+use filetime::{set_file_atime, set_file_mtime, FileTime};
 use serde_json::json;
 use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use toml::de::{DeTable, DeValue};
+use xxhash_rust::xxh3::Xxh3;
 
 // Structures used for a copy job configuration and the global configuration:
 // values provided in CopyJobConfig default to the ones provided globally in
 // the CopyJobGlobalConfig object, and override them if different
 #[derive(Debug)]
 struct CopyJobConfig {
-    job_name: String,             // the job name
-    source_dir: PathBuf,          // source directory
-    destination_dir: PathBuf,     // destination directory
-    include_pattern: String,      // RE pattern of filenames to include
-    exclude_pattern: String,      // RE pattern of filenames to exclude
-    excludedir_pattern: String,   // RE pattern of directories to skip
-    recursive: bool,              // recurse directories
-    case_sensitive: bool,         // consider filenames as case sensitive
-    follow_symlinks: bool,        // follow symlinks
-    overwrite: bool,              // possibly overwrite destination
-    skip_newer: bool,             // do not overwrite more recent files
-    check_content: bool,          // check whether contents are the same
-    remove_others_matching: bool, // remove matching files not present in source
-    create_directories: bool,     // create non-existing directories
-    keep_structure: bool,         // keep directory structure as in source
-    trash_on_delete: bool,        // use garbage bin instead of deleting
-    trash_on_overwrite: bool,     // send to garbage bin before overwrite
-    halt_on_errors: bool,         // exit job if an error occurs
+    job_name: String,              // the job name
+    source_dir: PathBuf,           // source directory
+    destination_dir: PathBuf,      // destination directory
+    include_pattern: String,       // RE pattern of filenames to include
+    exclude_pattern: String,       // RE pattern of filenames to exclude
+    excludedir_pattern: String,    // RE pattern of directories to skip
+    recursive: bool,               // recurse directories
+    case_sensitive: bool,          // consider filenames as case sensitive
+    follow_symlinks: bool,         // follow symlinks
+    overwrite: bool,               // possibly overwrite destination
+    skip_newer: bool,              // do not overwrite more recent files
+    check_content: bool,           // check whether contents are the same
+    remove_others_matching: bool,  // remove matching files not present in source
+    create_directories: bool,      // create non-existing directories
+    keep_structure: bool,          // keep directory structure as in source
+    trash_on_delete: bool,         // use garbage bin instead of deleting
+    trash_on_overwrite: bool,      // send to garbage bin before overwrite
+    halt_on_errors: bool,          // exit job if an error occurs
+    dry_run: bool,                 // only report what would be done
+    preserve_mtime: bool,          // preserve modification time
+    preserve_atime: bool,          // preserve access time
+    preserve_permissions: bool,    // preserve permissions
+    preserve_owner: bool,          // preserve owner and group
+    atomic_copy: bool,             // copy to temporary file, then rename
+    verify_after_copy: bool,       // compare contents after copying
+    verify_retries: u32,           // copy attempts after failed verification
+    hash_algorithm: HashAlgorithm, // algorithm used to compare contents
+    parallelism: usize,            // number of files copied concurrently
+    depends_on: Vec<String>,       // jobs that must complete successfully first
 }
 
 #[derive(Debug)]
@@ -67,13 +86,52 @@ struct CopyJobGlobalConfig {
     trash_on_delete: bool,              // use garbage bin instead of deleting
     trash_on_overwrite: bool,           // send to garbage bin before overwrite
     halt_on_errors: bool,               // exit job if an error occurs
+    dry_run: bool,                      // only report what would be done
+    preserve_mtime: bool,               // preserve modification time
+    preserve_atime: bool,               // preserve access time
+    preserve_permissions: bool,         // preserve permissions
+    preserve_owner: bool,               // preserve owner and group
+    atomic_copy: bool,                  // copy to temporary file, then rename
+    verify_after_copy: bool,            // compare contents after copying
+    verify_retries: u32,                // copy attempts after failed verification
+    hash_algorithm: HashAlgorithm,      // algorithm used to compare contents
+    parallelism: usize,                 // number of files copied concurrently
+    max_parallel_jobs: usize,           // number of jobs run concurrently
+    hash_cache: Option<PathBuf>,        // file where digests are cached
+    log_file: Option<PathBuf>,          // file where messages are logged
+    log_max_size: u64,                  // size that causes log rotation (0: none)
+    log_level: Option<Verbosity>,       // level of detail of messages
+    lock_file: Option<PathBuf>,         // file locked while running jobs
 
     // the following parameters are defined through CLI arguments only
     config_file: PathBuf,  // configuration file path
-    verbose: bool,         // provide output while running
+    verbosity: Verbosity,  // level of detail of console output
     parsable_output: bool, // provide machine-readable output
 }
 
+// Types of values that can be assigned to configuration keys, used to check
+// configuration files both at the global and at the job level
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfigValueType {
+    Boolean,    // true or false
+    Integer,    // a non negative integer
+    String,     // a single string
+    StringList, // a list of strings
+    StringMap,  // a table whose values are strings
+    TableList,  // an array of tables
+}
+
+// A problem found while validating a configuration file, along with the
+// file and the position (line and column, both starting at 1) where it occurs
+#[derive(Debug, PartialEq)]
+struct ConfigProblem {
+    file: PathBuf,
+    line: usize,
+    column: usize,
+    key: String,
+    message: String,
+}
+
 // Holds a result for file op to be choosen among the following ones: it
 // does not contain the word Result in the definition as it is not related
 // to the plethora of *::Result outcomes used in Rust (though it indicates
@@ -100,6 +158,11 @@ const FOERR_SOURCE_NOT_EXISTS: u64 = 1041;
 const FOERR_SOURCE_IS_DIR: u64 = 1042;
 const FOERR_SOURCE_IS_SYMLINK: u64 = 1043;
 const FOERR_SOURCE_NOT_ACCESSIBLE: u64 = 1044;
+const FOERR_CANNOT_SET_TIMES: u64 = 1051;
+const FOERR_CANNOT_SET_PERMISSIONS: u64 = 1052;
+const FOERR_CANNOT_SET_OWNER: u64 = 1053;
+const FOERR_VERIFICATION_FAILED: u64 = 1061;
+const FOERR_CANNOT_VERIFY: u64 = 1062;
 
 // values for Outcome::Error (run_single_job, run_jobs)
 const CJERR_GENERIC_FAILURE: u64 = 2001;
@@ -108,25 +171,72 @@ const CJERR_DESTINATION_DIR_NOT_EXISTS: u64 = 2012;
 const CJERR_NO_SOURCE_FILES: u64 = 2013;
 const CJERR_CANNOT_DETERMINE_DESTFILE: u64 = 2021;
 const CJERR_HALT_ON_COPY_ERROR: u64 = 2041;
+const CJERR_DEPENDENCY_FAILED: u64 = 2051;
+const CJERR_INTERRUPTED: u64 = 2061;
 
 // values for generic outcomes
 const ERR_OK: u64 = 0;
 const ERR_GENERIC: u64 = 9999;
 const ERR_INVALID_CONFIG_FILE: u64 = 9998;
+const ERR_INVALID_PATTERN: u64 = 9997;
+const ERR_CANNOT_SAVE_CACHE: u64 = 9996;
+const ERR_UNDEFINED_VARIABLE: u64 = 9995;
+const ERR_CANNOT_WRITE_REPORT: u64 = 9994;
+const ERR_CANNOT_OPEN_LOG: u64 = 9993;
+const ERR_LOCK_CONTENTION: u64 = 9992;
+const ERR_INTERRUPTED: u64 = 9991;
+const ERR_JOBS_FAILED: u64 = 9990;
+const ERR_FILES_FAILED: u64 = 9989;
+
+// exit codes of the process, in order of severity: when several problems
+// occur the most severe one determines the exit code (2 is used by the
+// command line parser for invalid arguments)
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FILES_FAILED: i32 = 1;
+const EXIT_JOBS_FAILED: i32 = 3;
+const EXIT_INVALID_CONFIG: i32 = 4;
+const EXIT_LOCK_CONTENTION: i32 = 5;
+const EXIT_INTERRUPTED: i32 = 6;
+
+// size of buffer used to read files when computing digests
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+// size of chunks used to copy files when reporting progress
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+// minimum interval between updates of the progress line
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+// suffix of temporary files used for atomic copies
+const TEMPORARY_FILE_SUFFIX: &str = ".copyjob-tmp";
+
+// number of older log files kept when the log file is rotated
+const LOG_ROTATED_FILES: usize = 5;
 
 // context identifiers for output
 const CONTEXT_MAIN: &str = "MAIN";
 const CONTEXT_JOB: &str = "JOB";
 const CONTEXT_TASK: &str = "TASK";
 
+// version of the schema of machine readable events (jsonl-v2 output format)
+const OUTPUT_SCHEMA_VERSION: u64 = 2;
+
 // operation identifiers for output
 const OPERATION_JOB_COPY: &str = "COPY";
 const OPERATION_JOB_DEL: &str = "DEL";
+const OPERATION_JOB_DRYRUN_COPY: &str = "DRYRUN_COPY";
+const OPERATION_JOB_DRYRUN_DEL: &str = "DRYRUN_DEL";
+const OPERATION_JOB_CLEANUP: &str = "CLEANUP";
 const OPERATION_JOB_BEGIN: &str = "BEGIN_JOB";
 const OPERATION_JOB_END: &str = "END_JOB";
 // const OPERATION_MAIN_BEGIN: &str = "BEGIN_MAIN";
 const OPERATION_MAIN_END: &str = "END_MAIN";
 const OPERATION_CONFIG: &str = "CONFIG";
+const OPERATION_VALIDATE: &str = "VALIDATE";
+const OPERATION_CACHE: &str = "CACHE";
+const OPERATION_SUMMARY: &str = "SUMMARY";
+const OPERATION_REPORT: &str = "REPORT";
+const OPERATION_LOG: &str = "LOG";
 
 // Some constants used within the code
 lazy_static! {
@@ -165,6 +275,11 @@ lazy_static! {
         _tmap.insert(FOERR_SOURCE_IS_DIR, "FOERR_SOURCE_IS_DIR");
         _tmap.insert(FOERR_SOURCE_IS_SYMLINK, "FOERR_SOURCE_IS_SYMLINK");
         _tmap.insert(FOERR_SOURCE_NOT_ACCESSIBLE, "FOERR_SOURCE_NOT_ACCESSIBLE");
+        _tmap.insert(FOERR_CANNOT_SET_TIMES, "FOERR_CANNOT_SET_TIMES");
+        _tmap.insert(FOERR_CANNOT_SET_PERMISSIONS, "FOERR_CANNOT_SET_PERMISSIONS");
+        _tmap.insert(FOERR_CANNOT_SET_OWNER, "FOERR_CANNOT_SET_OWNER");
+        _tmap.insert(FOERR_VERIFICATION_FAILED, "FOERR_VERIFICATION_FAILED");
+        _tmap.insert(FOERR_CANNOT_VERIFY, "FOERR_CANNOT_VERIFY");
 
         _tmap.insert(CJERR_GENERIC_FAILURE, "CJERR_GENERIC_FAILURE");
         _tmap.insert(CJERR_SOURCE_DIR_NOT_EXISTS, "CJERR_SOURCE_DIR_NOT_EXISTS");
@@ -172,8 +287,19 @@ lazy_static! {
         _tmap.insert(CJERR_NO_SOURCE_FILES, "CJERR_NO_SOURCE_FILES");
         _tmap.insert(CJERR_CANNOT_DETERMINE_DESTFILE, "CJERR_CANNOT_DETERMINE_DESTFILE");
         _tmap.insert(CJERR_HALT_ON_COPY_ERROR, "CJERR_HALT_ON_COPY_ERROR");
+        _tmap.insert(CJERR_DEPENDENCY_FAILED, "CJERR_DEPENDENCY_FAILED");
+        _tmap.insert(CJERR_INTERRUPTED, "CJERR_INTERRUPTED");
 
         _tmap.insert(ERR_INVALID_CONFIG_FILE, "ERR_INVALID_CONFIG");
+        _tmap.insert(ERR_INVALID_PATTERN, "ERR_INVALID_PATTERN");
+        _tmap.insert(ERR_CANNOT_SAVE_CACHE, "ERR_CANNOT_SAVE_CACHE");
+        _tmap.insert(ERR_UNDEFINED_VARIABLE, "ERR_UNDEFINED_VARIABLE");
+        _tmap.insert(ERR_CANNOT_WRITE_REPORT, "ERR_CANNOT_WRITE_REPORT");
+        _tmap.insert(ERR_CANNOT_OPEN_LOG, "ERR_CANNOT_OPEN_LOG");
+        _tmap.insert(ERR_LOCK_CONTENTION, "ERR_LOCK_CONTENTION");
+        _tmap.insert(ERR_INTERRUPTED, "ERR_INTERRUPTED");
+        _tmap.insert(ERR_JOBS_FAILED, "ERR_JOBS_FAILED");
+        _tmap.insert(ERR_FILES_FAILED, "ERR_FILES_FAILED");
         _tmap.insert(ERR_GENERIC, "ERR_GENERIC");
         _tmap.insert(ERR_OK, "OK");
         _tmap
@@ -197,6 +323,11 @@ lazy_static! {
         _tmap.insert(FOERR_SOURCE_IS_DIR, "file operation: source file is a directory");
         _tmap.insert(FOERR_SOURCE_IS_SYMLINK, "file operation: source file is a symbolic link");
         _tmap.insert(FOERR_SOURCE_NOT_ACCESSIBLE, "file operation: source file is not accessible");
+        _tmap.insert(FOERR_CANNOT_SET_TIMES, "file operation: cannot set file times");
+        _tmap.insert(FOERR_CANNOT_SET_PERMISSIONS, "file operation: cannot set file permissions");
+        _tmap.insert(FOERR_CANNOT_SET_OWNER, "file operation: cannot set file owner");
+        _tmap.insert(FOERR_VERIFICATION_FAILED, "file operation: copied file differs from source");
+        _tmap.insert(FOERR_CANNOT_VERIFY, "file operation: cannot read files to verify copy");
 
         _tmap.insert(CJERR_GENERIC_FAILURE, "copy job: generic failure");
         _tmap.insert(CJERR_SOURCE_DIR_NOT_EXISTS, "copy job: source directory does not exist");
@@ -204,29 +335,143 @@ lazy_static! {
         _tmap.insert(CJERR_NO_SOURCE_FILES, "copy job: no source files found");
         _tmap.insert(CJERR_CANNOT_DETERMINE_DESTFILE, "copy job: cannot determine source");
         _tmap.insert(CJERR_HALT_ON_COPY_ERROR, "copy job: ending job after copy error");
+        _tmap.insert(CJERR_DEPENDENCY_FAILED, "copy job: skipped because a job it depends on failed or is not active");
+        _tmap.insert(CJERR_INTERRUPTED, "copy job: interrupted by a signal");
 
         _tmap.insert(ERR_INVALID_CONFIG_FILE, "application: invalid config file");
+        _tmap.insert(ERR_INVALID_PATTERN, "application: invalid pattern in config file");
+        _tmap.insert(ERR_CANNOT_SAVE_CACHE, "application: cannot save hash cache");
+        _tmap.insert(ERR_UNDEFINED_VARIABLE, "application: undefined variable in config file");
+        _tmap.insert(ERR_CANNOT_WRITE_REPORT, "application: cannot write report");
+        _tmap.insert(ERR_CANNOT_OPEN_LOG, "application: cannot open log file");
+        _tmap.insert(ERR_LOCK_CONTENTION, "application: configuration file in use by another run");
+        _tmap.insert(ERR_INTERRUPTED, "application: interrupted by a signal");
+        _tmap.insert(ERR_JOBS_FAILED, "application: some jobs failed");
+        _tmap.insert(ERR_FILES_FAILED, "application: some files could not be copied or removed");
         _tmap.insert(ERR_GENERIC, "application: generic failure");
         _tmap.insert(ERR_OK, "application: operation succeeded");
         _tmap
     };
 
+    // known configuration keys and the expected types of their values, at
+    // global level and within job definitions respectively
+    static ref CONFIG_GLOBAL_KEYS: HashMap<&'static str, ConfigValueType> = {
+        let mut _tmap = HashMap::new();
+        _tmap.insert("include", ConfigValueType::StringList);
+        _tmap.insert("strict_variables", ConfigValueType::Boolean);
+        _tmap.insert("active_jobs", ConfigValueType::StringList);
+        _tmap.insert("variables", ConfigValueType::StringMap);
+        _tmap.insert("recursive", ConfigValueType::Boolean);
+        _tmap.insert("case_sensitive", ConfigValueType::Boolean);
+        _tmap.insert("follow_symlinks", ConfigValueType::Boolean);
+        _tmap.insert("overwrite", ConfigValueType::Boolean);
+        _tmap.insert("skip_newer", ConfigValueType::Boolean);
+        _tmap.insert("check_content", ConfigValueType::Boolean);
+        _tmap.insert("remove_others_matching", ConfigValueType::Boolean);
+        _tmap.insert("create_directories", ConfigValueType::Boolean);
+        _tmap.insert("keep_structure", ConfigValueType::Boolean);
+        _tmap.insert("trash_on_delete", ConfigValueType::Boolean);
+        _tmap.insert("trash_on_overwrite", ConfigValueType::Boolean);
+        _tmap.insert("halt_on_errors", ConfigValueType::Boolean);
+        _tmap.insert("dry_run", ConfigValueType::Boolean);
+        _tmap.insert("preserve_mtime", ConfigValueType::Boolean);
+        _tmap.insert("preserve_atime", ConfigValueType::Boolean);
+        _tmap.insert("preserve_permissions", ConfigValueType::Boolean);
+        _tmap.insert("preserve_owner", ConfigValueType::Boolean);
+        _tmap.insert("atomic_copy", ConfigValueType::Boolean);
+        _tmap.insert("verify_after_copy", ConfigValueType::Boolean);
+        _tmap.insert("verify_retries", ConfigValueType::Integer);
+        _tmap.insert("hash_algorithm", ConfigValueType::String);
+        _tmap.insert("parallelism", ConfigValueType::Integer);
+        _tmap.insert("hash_cache", ConfigValueType::String);
+        _tmap.insert("max_parallel_jobs", ConfigValueType::Integer);
+        _tmap.insert("log_file", ConfigValueType::String);
+        _tmap.insert("log_max_size", ConfigValueType::Integer);
+        _tmap.insert("log_level", ConfigValueType::String);
+        _tmap.insert("lock_file", ConfigValueType::String);
+        _tmap.insert("template", ConfigValueType::TableList);
+        _tmap.insert("job", ConfigValueType::TableList);
+        _tmap
+    };
+    static ref CONFIG_JOB_KEYS: HashMap<&'static str, ConfigValueType> = {
+        let mut _tmap = HashMap::new();
+        _tmap.insert("name", ConfigValueType::String);
+        _tmap.insert("source", ConfigValueType::String);
+        _tmap.insert("destination", ConfigValueType::String);
+        _tmap.insert("patterns_include", ConfigValueType::StringList);
+        _tmap.insert("patterns_exclude", ConfigValueType::StringList);
+        _tmap.insert("patterns_exclude_dir", ConfigValueType::StringList);
+        _tmap.insert("recursive", ConfigValueType::Boolean);
+        _tmap.insert("case_sensitive", ConfigValueType::Boolean);
+        _tmap.insert("follow_symlinks", ConfigValueType::Boolean);
+        _tmap.insert("overwrite", ConfigValueType::Boolean);
+        _tmap.insert("skip_newer", ConfigValueType::Boolean);
+        _tmap.insert("check_content", ConfigValueType::Boolean);
+        _tmap.insert("remove_others_matching", ConfigValueType::Boolean);
+        _tmap.insert("create_directories", ConfigValueType::Boolean);
+        _tmap.insert("keep_structure", ConfigValueType::Boolean);
+        _tmap.insert("trash_on_delete", ConfigValueType::Boolean);
+        _tmap.insert("trash_on_overwrite", ConfigValueType::Boolean);
+        _tmap.insert("halt_on_errors", ConfigValueType::Boolean);
+        _tmap.insert("dry_run", ConfigValueType::Boolean);
+        _tmap.insert("preserve_mtime", ConfigValueType::Boolean);
+        _tmap.insert("preserve_atime", ConfigValueType::Boolean);
+        _tmap.insert("preserve_permissions", ConfigValueType::Boolean);
+        _tmap.insert("preserve_owner", ConfigValueType::Boolean);
+        _tmap.insert("atomic_copy", ConfigValueType::Boolean);
+        _tmap.insert("verify_after_copy", ConfigValueType::Boolean);
+        _tmap.insert("verify_retries", ConfigValueType::Integer);
+        _tmap.insert("hash_algorithm", ConfigValueType::String);
+        _tmap.insert("parallelism", ConfigValueType::Integer);
+        _tmap.insert("depends_on", ConfigValueType::StringList);
+        _tmap.insert("variables", ConfigValueType::StringMap);
+        _tmap.insert("extends", ConfigValueType::String);
+        _tmap.insert("patterns_include_append", ConfigValueType::StringList);
+        _tmap.insert("patterns_exclude_append", ConfigValueType::StringList);
+        _tmap.insert("patterns_exclude_dir_append", ConfigValueType::StringList);
+        _tmap
+    };
+
+    // keys that must be present in every job definition
+    static ref CONFIG_JOB_MANDATORY_KEYS: Vec<&'static str> = vec![
+        "name",
+        "source",
+        "destination",
+        "patterns_include",
+    ];
+
     static ref STR_MATCH_NO_FILE: String = String::from(r"^\*$");
 
     static ref RE_VARNAME: Regex = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
     static ref RE_JOBNAME: Regex = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
-    static ref RE_MATCH_NO_FILE: Regex = Regex::new(&STR_MATCH_NO_FILE).unwrap();
 
     // variable mention expressions: *_LOC is the mention of a variable
     // defined in the configuration file, *_ENV is the mention of a variable
-    // defined in the system environment
-    static ref RE_VARMENTION_LOC: Regex = Regex::new(r"[%]\{([a-zA-Z_][a-zA-Z0-9_]*)\}").unwrap();
-    static ref RE_VARMENTION_ENV: Regex = Regex::new(r"[\$]\{([a-zA-Z_][a-zA-Z0-9_]*)\}").unwrap();
+    // defined in the system environment; the name can be followed by an
+    // operator (:- or :?) and its argument, as in shell parameter expansion
+    static ref RE_VARMENTION_LOC: Regex =
+        Regex::new(r"[%]\{([a-zA-Z_][a-zA-Z0-9_]*)(?::([-?])([^}]*))?\}").unwrap();
+    static ref RE_VARMENTION_ENV: Regex =
+        Regex::new(r"[\$]\{([a-zA-Z_][a-zA-Z0-9_]*)(?::([-?])([^}]*))?\}").unwrap();
 
-    // these must have a star corresponding to the internal group of the
-    // corresponding RE_VARMENTION_* instance
-    static ref FMT_VARMENTION_LOC: String = String::from("%{*}");
-    static ref FMT_VARMENTION_ENV: String = String::from("${*}");
+    // mention of the current time using a strftime-like format
+    static ref RE_NOWMENTION: Regex = Regex::new(r"[%]\{NOW:([^}]*)\}").unwrap();
+
+    // placeholders that are available as local variables, unless they are
+    // redefined in the configuration file, and their formats
+    static ref TIMESTAMP_VARIABLES: Vec<(&'static str, &'static str)> = vec![
+        ("DATE", "%Y-%m-%d"),
+        ("TIME", "%H-%M-%S"),
+        ("YEAR", "%Y"),
+        ("MONTH", "%m"),
+        ("DAY", "%d"),
+        ("WEEKDAY", "%A"),
+    ];
+
+    // the time at which copyjob was started, shared by all jobs in a run: if
+    // the local time zone cannot be determined UTC is used instead
+    static ref RUN_TIMESTAMP: OffsetDateTime =
+        OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
 }
 
 // helper to convert a list of regexp patterns into a single ORed regexp
@@ -234,15 +479,566 @@ fn combine_regexp_patterns(res: &[String]) -> String {
     format!("({})", res.join("|"))
 }
 
-// Helper to calculate hash for a single file
+// helper to extract a short, single line message from a regexp error
+fn regexp_error_message(err: &regex::Error) -> String {
+    let msg = err.to_string();
+    match msg.rsplit_once("error: ") {
+        Some((_, m)) => String::from(m.trim()),
+        None => String::from(msg.trim()),
+    }
+}
+
+// helper to replace mentions of environment or local variables
+fn replace_variables_in_string(
+    pattern: &Regex,
+    source: &str,
+    vars: &HashMap<String, String>,
+    strict: bool,
+) -> Result<String, (String, String)> {
+    // local helpers:
+
+    // l1. replace the mentions found in a string, expanding in turn the
+    // mentions found in the values of variables and in default values: the
+    // variables being expanded are tracked, so that a variable whose value
+    // mentions itself, directly or through other variables, is an error
+    fn _rv_expand(
+        pattern: &Regex,
+        source: &str,
+        vars: &HashMap<String, String>,
+        strict: bool,
+        expanding: &mut Vec<String>,
+    ) -> Result<String, (String, String)> {
+        let mut result = String::new();
+        let mut last = 0;
+        for caps in pattern.captures_iter(source) {
+            let mention = caps.get(0).unwrap();
+            let varname = caps.get(1).map_or("", |m| m.as_str());
+            let argument = caps.get(3).map_or("", |m| m.as_str());
+            if expanding.iter().any(|v| v == varname) {
+                return Err((String::from(varname), String::from("circular reference")));
+            }
+            let value = vars.get(varname);
+            let unset = value.is_none_or(|v| v.is_empty());
+            let replacement = match caps.get(2).map(|m| m.as_str()) {
+                Some("-") if unset => String::from(argument),
+                Some("?") if unset => {
+                    let message = if argument.is_empty() {
+                        "undefined variable"
+                    } else {
+                        argument
+                    };
+                    return Err((String::from(varname), String::from(message)));
+                }
+                _ => match value {
+                    Some(value) => value.clone(),
+                    None if strict => {
+                        return Err((String::from(varname), String::from("undefined variable")));
+                    }
+                    None => String::new(),
+                },
+            };
+            expanding.push(String::from(varname));
+            let replacement = _rv_expand(pattern, &replacement, vars, strict, expanding)?;
+            expanding.pop();
+            result.push_str(&source[last..mention.start()]);
+            result.push_str(&replacement);
+            last = mention.end();
+        }
+        result.push_str(&source[last..]);
+        Ok(result)
+    }
+
+    // mimick shell by replacing undefined variables with the empty string,
+    // unless in strict mode: since the same function is used for both local
+    // and environment vars, this represents a difference with the Python
+    // version, that considered mentioning an undefined local variable a
+    // fatal error; as in shell, NAME:-default is replaced by the default
+    // value and NAME:?message causes an error when the variable is either
+    // undefined or empty, and the error consists of the variable name and
+    // of the message
+    // WARNING: this actually assumes that the regular expression pattern
+    //          "[%$]\{[a-zA-Z_][a-zA-Z0-9_]*\}" cannot appear in the source or
+    //          the destination directory within job definitions
+    _rv_expand(pattern, source, vars, strict, &mut Vec::new())
+}
+
+// helper to replace special path markers at the beginning of a path
+fn replace_markers_in_string(source: &str, user_home: &Path, config_file_dir: &Path) -> String {
+    let mut result = String::from(source);
+    for (mkey, mlist) in DIR_MARKERS.clone().iter() {
+        for marker in mlist {
+            if result.starts_with(marker) {
+                match *mkey {
+                    "USER-HOME" => {
+                        // the slash is preserved
+                        result =
+                            user_home.to_string_lossy().to_string() + &String::from(&result[1..]);
+                    }
+                    "CONFIG-FILE-DIR" => {
+                        result = config_file_dir.to_string_lossy().to_string()
+                            + &String::from(&result[1..]); // to preserve the slash
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    result
+}
+
+// helper to format a timestamp: the supported specifiers are a subset of
+// the ones of strftime, and unknown specifiers are left untouched
+fn format_timestamp(timestamp: &OffsetDateTime, format: &str) -> String {
+    let mut result = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => result.push_str(&format!("{:04}", timestamp.year())),
+            Some('y') => result.push_str(&format!("{:02}", timestamp.year() % 100)),
+            Some('m') => result.push_str(&format!("{:02}", u8::from(timestamp.month()))),
+            Some('d') => result.push_str(&format!("{:02}", timestamp.day())),
+            Some('j') => result.push_str(&format!("{:03}", timestamp.ordinal())),
+            Some('H') => result.push_str(&format!("{:02}", timestamp.hour())),
+            Some('M') => result.push_str(&format!("{:02}", timestamp.minute())),
+            Some('S') => result.push_str(&format!("{:02}", timestamp.second())),
+            Some('u') => result.push_str(&timestamp.weekday().number_from_monday().to_string()),
+            Some('V') => result.push_str(&format!("{:02}", timestamp.iso_week())),
+            Some('A') => result.push_str(&timestamp.weekday().to_string()),
+            Some('a') => result.extend(timestamp.weekday().to_string().chars().take(3)),
+            Some('B') => result.push_str(&timestamp.month().to_string()),
+            Some('b') => result.extend(timestamp.month().to_string().chars().take(3)),
+            Some('z') => {
+                let (hours, minutes, _) = timestamp.offset().as_hms();
+                let sign = if timestamp.offset().is_negative() {
+                    '-'
+                } else {
+                    '+'
+                };
+                result.push_str(&format!("{sign}{:02}{:02}", hours.abs(), minutes.abs()));
+            }
+            Some('%') => result.push('%'),
+            Some(other) => {
+                result.push('%');
+                result.push(other);
+            }
+            None => result.push('%'),
+        }
+    }
+    result
+}
+
+// helper to build the map of local variables, including the placeholders
+// for the time of the run that have not been redefined
+fn variables_with_timestamps(
+    variables: &HashMap<String, String>,
+    timestamp: &OffsetDateTime,
+) -> HashMap<String, String> {
+    let mut result: HashMap<String, String> = TIMESTAMP_VARIABLES
+        .iter()
+        .map(|(name, format)| (String::from(*name), format_timestamp(timestamp, format)))
+        .collect();
+    result.extend(variables.clone());
+    result
+}
+
+// helper to replace mentions of the current time in a string
+fn replace_timestamps_in_string(source: &str, timestamp: &OffsetDateTime) -> String {
+    RE_NOWMENTION
+        .replace_all(source, |caps: &regex::Captures| {
+            format_timestamp(timestamp, caps.get(1).map_or("", |m| m.as_str()))
+        })
+        .to_string()
+}
+
+// helper to normalize path slashes (forward+back & multiple)
+fn normalize_path_slashes(path: &str) -> String {
+    if cfg!(windows) {
+        Regex::new("\\[\\]+")
+            .unwrap() // cannot panic for we know the RE is correct
+            .replace_all(&path.replace("/", "\\"), "\\")
+            .to_string()
+    } else {
+        Regex::new("/[/]+")
+            .unwrap() // cannot panic for we know the RE is correct
+            .replace_all(&path.replace("\\", "/"), "/")
+            .to_string()
+    }
+}
+
+// helper to collect the environment variables of the running process
+fn environment_variables() -> HashMap<String, String> {
+    env::vars_os()
+        .map(|(var, value)| {
+            (
+                var.to_string_lossy().to_string(),
+                value.to_string_lossy().to_string(),
+            )
+        })
+        .collect()
+}
+
+// helper to expand a value that represents a path: local variables are
+// replaced first, then mentions of the current time, then environment
+// variables, and finally the special markers at the beginning of the path
+fn expand_path_value(
+    value: &str,
+    variables: &HashMap<String, String>,
+    sys_variables: &HashMap<String, String>,
+    strict: bool,
+    user_home: &Path,
+    config_file_dir: &Path,
+) -> Result<String, (String, String)> {
+    let mut s = replace_variables_in_string(&RE_VARMENTION_LOC, value, variables, strict)?;
+    s = replace_timestamps_in_string(&s, &RUN_TIMESTAMP);
+    s = replace_variables_in_string(&RE_VARMENTION_ENV, &s, sys_variables, strict)?;
+    Ok(replace_markers_in_string(&s, user_home, config_file_dir))
+}
+
+// helper to determine the path of an included configuration file: local
+// variables of the including file and environment variables are replaced,
+// and so are the special markers, where @/ refers to the directory of the
+// including file; relative paths are intended as relative to it as well
+fn include_file_path(
+    spec: &str,
+    including_file: &Path,
+    variables: &HashMap<String, String>,
+    strict: bool,
+) -> Result<PathBuf, (String, String)> {
+    let config_file_dir = including_file.parent().unwrap_or(Path::new(""));
+    let s = expand_path_value(
+        spec,
+        &variables_with_timestamps(variables, &RUN_TIMESTAMP),
+        &environment_variables(),
+        strict,
+        &home_dir().unwrap_or_default(),
+        config_file_dir,
+    )?;
+    Ok(config_file_dir.join(normalize_path_slashes(&s)))
+}
+
+// helper to convert the configured parallelism into a number of workers:
+// zero stands for as many workers as the available CPUs, while negative
+// values are not accepted
+fn parallelism_from_value(value: i64) -> Option<usize> {
+    match value {
+        0 => Some(std::thread::available_parallelism().map_or(1, |n| n.get())),
+        n => usize::try_from(n).ok(),
+    }
+}
+
+// helper to sort jobs so that each job follows the jobs it depends on: the
+// original order is kept as much as possible, and dependencies on jobs that
+// are not in the list are ignored; if dependencies are circular, the names
+// of the jobs involved in one of the cycles are returned as an error
+fn dependency_order(
+    names: &[String],
+    dependencies: &[Vec<String>],
+) -> Result<Vec<usize>, Vec<String>> {
+    let requires: Vec<Vec<usize>> = dependencies
+        .iter()
+        .map(|deps| {
+            deps.iter()
+                .filter_map(|dep| names.iter().position(|name| name == dep))
+                .collect()
+        })
+        .collect();
+    let mut order: Vec<usize> = Vec::new();
+    while order.len() < names.len() {
+        match (0..names.len()).find(|index| {
+            !order.contains(index) && requires[*index].iter().all(|dep| order.contains(dep))
+        }) {
+            Some(index) => order.push(index),
+            None => {
+                // every remaining job requires another remaining job, thus
+                // following requirements from any of them leads to a cycle
+                let mut path: Vec<usize> = Vec::new();
+                let mut index = (0..names.len())
+                    .find(|index| !order.contains(index))
+                    .unwrap();
+                while !path.contains(&index) {
+                    path.push(index);
+                    index = *requires[index]
+                        .iter()
+                        .find(|dep| !order.contains(dep))
+                        .unwrap();
+                }
+                let start = path.iter().position(|i| *i == index).unwrap();
+                let mut cycle: Vec<String> =
+                    path[start..].iter().map(|i| names[*i].clone()).collect();
+                cycle.push(names[index].clone());
+                return Err(cycle);
+            }
+        }
+    }
+    Ok(order)
+}
+
+// helper to follow the chain of templates starting from the named one: the
+// chain is returned from the closest to the farthest template, and stops at
+// the first undefined template; if the chain loops, the names of templates
+// that are part of the cycle are returned as an error
+fn template_chain(
+    name: &str,
+    extends: &HashMap<String, Option<String>>,
+) -> Result<Vec<String>, Vec<String>> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut current = Some(String::from(name));
+    while let Some(name) = current {
+        if let Some(start) = chain.iter().position(|n| *n == name) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(name);
+            return Err(cycle);
+        }
+        match extends.get(&name) {
+            Some(parent) => {
+                current = parent.clone();
+                chain.push(name);
+            }
+            None => break,
+        }
+    }
+    Ok(chain)
+}
+
+// Resolve job templates in a configuration document: every job receives the
+// parameters of the templates it extends, directly or through other ones,
+// unless it defines them itself; the `*_append` pattern lists are appended
+// to the inherited ones instead of replacing them, and local variables are
+// merged with the inherited ones. Templates are removed
+// from the document, and jobs are left with the resolved parameters only.
+// On failure, the offending key is returned to be used in error messages.
+fn resolve_templates(document: &mut toml::Table) -> Result<(), String> {
+    // local helpers:
+
+    // l1. apply the parameters of a template or job on top of the ones that
+    // have already been collected, merging local variables and appending the
+    // *_append lists last
+    fn _rt_apply(
+        resolved: &mut toml::Table,
+        layer: &toml::Table,
+        section: &str,
+    ) -> Result<(), String> {
+        for (key, value) in layer.iter() {
+            if key == "extends"
+                || key.ends_with("_append")
+                || (key == "name" && section == "template")
+            {
+                continue;
+            }
+            if let (Some(toml::Value::Table(inherited)), toml::Value::Table(variables)) =
+                (resolved.get_mut(key), value)
+            {
+                if key == "variables" {
+                    inherited.extend(variables.clone());
+                    continue;
+                }
+            }
+            resolved.insert(key.clone(), value.clone());
+        }
+        for (key, value) in layer.iter() {
+            if let Some(base) = key.strip_suffix("_append") {
+                let Some(items) = value.as_array() else {
+                    return Err(format!("{section}/{key}"));
+                };
+                match resolved
+                    .entry(base)
+                    .or_insert(toml::Value::Array(Vec::new()))
+                    .as_array_mut()
+                {
+                    Some(inherited) => inherited.extend(items.iter().cloned()),
+                    None => return Err(format!("{section}/{base}")),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // collect templates by name, checking their keys
+    let mut templates: HashMap<String, toml::Table> = HashMap::new();
+    let mut extends: HashMap<String, Option<String>> = HashMap::new();
+    if let Some(value) = document.remove("template") {
+        let Some(items) = value.as_array() else {
+            return Err(String::from("template"));
+        };
+        for item in items {
+            let Some(template) = item.as_table() else {
+                return Err(String::from("template"));
+            };
+            for key in template.keys() {
+                if !CONFIG_JOB_KEYS.contains_key(key.as_str()) {
+                    return Err(format!("template/{key}"));
+                }
+            }
+            let name = template
+                .get("name")
+                .and_then(|v| v.as_str())
+                .filter(|name| RE_JOBNAME.is_match(name))
+                .ok_or("template/name")?;
+            let parent = match template.get("extends") {
+                Some(v) => Some(String::from(v.as_str().ok_or("template/extends")?)),
+                None => None,
+            };
+            templates.insert(String::from(name), template.clone());
+            extends.insert(String::from(name), parent);
+        }
+    }
+
+    // templates must extend defined templates, and without cycles
+    for name in templates.keys() {
+        if let Some(Some(parent)) = extends.get(name) {
+            if !templates.contains_key(parent) {
+                return Err(format!("template/extends:{parent}"));
+            }
+        }
+        template_chain(name, &extends)
+            .map_err(|cycle| format!("template/extends:{}", cycle.join("->")))?;
+    }
+
+    // replace each job definition with its resolved version
+    if let Some(toml::Value::Array(jobs)) = document.get_mut("job") {
+        for item in jobs.iter_mut() {
+            let Some(job) = item.as_table_mut() else {
+                continue; // reported later as an invalid job
+            };
+            let chain = match job.get("extends") {
+                Some(v) => {
+                    let parent = v.as_str().ok_or("job/extends")?;
+                    if !templates.contains_key(parent) {
+                        return Err(format!("job/extends:{parent}"));
+                    }
+                    template_chain(parent, &extends).unwrap_or_default()
+                }
+                None => Vec::new(),
+            };
+            let mut resolved = toml::Table::new();
+            for name in chain.iter().rev() {
+                _rt_apply(&mut resolved, &templates[name], "template")?;
+            }
+            _rt_apply(&mut resolved, job, "job")?;
+            *job = resolved;
+        }
+    }
+    Ok(())
+}
+
+// Levels of detail of messages, each one including the previous ones: only
+// errors, the beginning and the end of jobs, the actions performed on each
+// file, and debugging information such as the reason why files are skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Verbosity {
+    Errors,
+    Jobs,
+    Files,
+    Debug,
+}
+
+impl Verbosity {
+    // names used in the configuration file
+    fn from_name(name: &str) -> Option<Verbosity> {
+        match name.to_lowercase().as_str() {
+            "errors" => Some(Verbosity::Errors),
+            "jobs" => Some(Verbosity::Jobs),
+            "files" => Some(Verbosity::Files),
+            "debug" => Some(Verbosity::Debug),
+            _ => None,
+        }
+    }
+
+    // severity shown in the log file for messages of this level
+    fn severity(&self) -> &'static str {
+        match self {
+            Verbosity::Errors => "ERROR",
+            Verbosity::Jobs | Verbosity::Files => "INFO",
+            Verbosity::Debug => "DEBUG",
+        }
+    }
+}
+
+// Hashing algorithms that can be used to compare file contents: SHA256 is
+// the default, BLAKE3 is a faster cryptographic hash, and XXH3 is a very
+// fast non-cryptographic hash, that is adequate to detect changes in files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum HashAlgorithm {
+    Sha256,
+    Blake3,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    // names used in the configuration file
+    fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name.to_lowercase().as_str() {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "xxh3" => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3::new()),
+        }
+    }
+}
+
+// Common interface of the hashing algorithms: data is fed in chunks, and
+// the digest is returned as raw bytes
+trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+impl ContentHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        Digest::finalize(*self).to_vec()
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake3::Hasher::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+impl ContentHasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.digest128().to_be_bytes().to_vec()
+    }
+}
+
+// Helper to calculate hash for a single file using the specified algorithm
 // see https://stackoverflow.com/a/71606608/5138770
-fn sha256_digest(path: &Path) -> std::io::Result<String> {
+fn file_digest(path: &Path, algorithm: HashAlgorithm) -> std::io::Result<String> {
     let input = File::open(path)?;
-    let mut reader = BufReader::new(input);
+    let mut reader = BufReader::with_capacity(HASH_BUFFER_SIZE, input);
 
     let digest = {
-        let mut hasher = Sha256::new();
-        let mut buffer = [0; 4096];
+        let mut hasher = algorithm.hasher();
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
         loop {
             let count = reader.read(&mut buffer)?;
             if count == 0 {
@@ -255,6 +1051,204 @@ fn sha256_digest(path: &Path) -> std::io::Result<String> {
     Ok(HEXLOWER.encode(digest.as_ref()))
 }
 
+// Persistent cache of file digests, so that files that have not changed
+// since the last run do not need to be hashed again: digests are stored
+// by algorithm and path, and are only considered valid if size, time of
+// last modification and inode of the file are still the same; the cache
+// can be shared among threads, so entries are protected by a mutex
+struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<(HashAlgorithm, String), HashCacheEntry>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HashCacheEntry {
+    size: u64,  // file size in bytes
+    mtime: u64, // time of last modification (ns from epoch)
+    inode: u64, // inode number (UNIX only, 0 elsewhere)
+    digest: String,
+}
+
+impl HashCacheEntry {
+    // tell whether an entry describes a file in the state of another entry
+    fn matches(&self, other: &HashCacheEntry) -> bool {
+        self.size == other.size && self.mtime == other.mtime && self.inode == other.inode
+    }
+}
+
+impl HashCache {
+    // load the cache from its file: a missing or unreadable cache file
+    // results in an empty cache, as does the request to rebuild it
+    fn load(path: &Path, rebuild: bool) -> HashCache {
+        let mut entries = HashMap::new();
+        if !rebuild {
+            if let Ok(text) = fs::read_to_string(path) {
+                if let Ok(data) = serde_json::from_str::<serde_json::Value>(&text) {
+                    for item in data["entries"].as_array().unwrap_or(&Vec::new()) {
+                        if let (
+                            Some(algorithm),
+                            Some(file),
+                            Some(size),
+                            Some(mtime),
+                            Some(inode),
+                            Some(digest),
+                        ) = (
+                            item["algorithm"]
+                                .as_str()
+                                .and_then(HashAlgorithm::from_name),
+                            item["path"].as_str(),
+                            item["size"].as_u64(),
+                            item["mtime"].as_u64(),
+                            item["inode"].as_u64(),
+                            item["digest"].as_str(),
+                        ) {
+                            entries.insert(
+                                (algorithm, String::from(file)),
+                                HashCacheEntry {
+                                    size,
+                                    mtime,
+                                    inode,
+                                    digest: String::from(digest),
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        HashCache {
+            path: PathBuf::from(path),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    // write the cache to its file, through a temporary file that replaces
+    // the previous version only when complete: entries of files that no
+    // longer exist, or that have changed since their digest was computed,
+    // are dropped, while all other entries are kept even if not used
+    fn save(&self) -> std::io::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let mut items: Vec<serde_json::Value> = Vec::new();
+        for ((algorithm, file), entry) in entries.iter() {
+            if !HashCache::current_entry(Path::new(file))
+                .is_some_and(|current| entry.matches(&current))
+            {
+                continue;
+            }
+            items.push(json!({
+                "algorithm": algorithm.name(),
+                "path": file,
+                "size": entry.size,
+                "mtime": entry.mtime,
+                "inode": entry.inode,
+                "digest": entry.digest,
+            }));
+        }
+        let temp_path = temporary_file_path(&self.path);
+        fs::write(
+            &temp_path,
+            json!({ "version": 1, "entries": items }).to_string(),
+        )?;
+        fs::rename(&temp_path, &self.path)
+    }
+
+    // build the entry that describes a file in its current state
+    fn current_entry(path: &Path) -> Option<HashCacheEntry> {
+        let stat = metadata(path).ok()?;
+        let mtime = stat
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_nanos();
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&stat);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Some(HashCacheEntry {
+            size: stat.len(),
+            mtime: u64::try_from(mtime).unwrap_or(u64::MAX),
+            inode,
+            digest: String::new(),
+        })
+    }
+
+    // retrieve the digest of a file, if still valid
+    fn lookup(&self, path: &Path, algorithm: HashAlgorithm) -> Option<String> {
+        let current = HashCache::current_entry(path)?;
+        let key = (algorithm, path.to_string_lossy().to_string());
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key)?;
+        if entry.matches(&current) {
+            Some(entry.digest.clone())
+        } else {
+            None
+        }
+    }
+
+    // record the digest of a file in its current state
+    fn store(&self, path: &Path, algorithm: HashAlgorithm, digest: &str) {
+        if let Some(mut entry) = HashCache::current_entry(path) {
+            entry.digest = String::from(digest);
+            let key = (algorithm, path.to_string_lossy().to_string());
+            self.entries.lock().unwrap().insert(key, entry);
+        }
+    }
+
+    // record the digest of a copied file, which is the one of its source
+    // if known, so that the copy is not hashed again on the next run
+    fn store_copy(&self, source: &Path, destination: &Path, algorithm: HashAlgorithm) {
+        if let Some(digest) = self.lookup(source, algorithm) {
+            self.store(destination, algorithm, &digest);
+        }
+    }
+}
+
+// helper to calculate the hash of a file, using the cache if available
+fn cached_file_digest(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    cache: Option<&HashCache>,
+) -> std::io::Result<String> {
+    if let Some(cache) = cache {
+        if let Some(digest) = cache.lookup(path, algorithm) {
+            return Ok(digest);
+        }
+    }
+    let digest = file_digest(path, algorithm)?;
+    if let Some(cache) = cache {
+        cache.store(path, algorithm, &digest);
+    }
+    Ok(digest)
+}
+
+// Sides of a copy operation, used to tell which file caused an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Source,
+    Destination,
+}
+
+// helper to compare the contents of two files: the comparison is performed
+// on file size first, and only if sizes are the same digests are computed;
+// either file being unreadable is reported as an error on the related side
+fn same_file_contents(
+    source: &Path,
+    destination: &Path,
+    algorithm: HashAlgorithm,
+    cache: Option<&HashCache>,
+) -> Result<bool, Side> {
+    let s_len = metadata(source).map_err(|_| Side::Source)?.len();
+    let d_len = metadata(destination).map_err(|_| Side::Destination)?.len();
+    if s_len != d_len {
+        return Ok(false);
+    }
+    let source_hash = cached_file_digest(source, algorithm, cache).map_err(|_| Side::Source)?;
+    let destination_hash =
+        cached_file_digest(destination, algorithm, cache).map_err(|_| Side::Destination)?;
+    Ok(source_hash == destination_hash)
+}
+
 // Helpers to simply convert an error code to text
 fn format_err_parsable(code: u64) -> String {
     if ERRS_PARSABLE.contains_key(&code) {
@@ -272,7 +1266,18 @@ fn format_err_verbose(code: u64) -> String {
     }
 }
 
-// helper to format a parsable output line consistently
+// tell whether an error code only means that a copy was not needed, or not
+// wanted, because of the destination file
+fn copy_skipped(code: u64) -> bool {
+    matches!(
+        code,
+        FOERR_DESTINATION_IS_NEWER | FOERR_DESTINATION_IS_IDENTICAL | FOERR_DESTINATION_EXISTS
+    )
+}
+
+// helper to format a parsable output line consistently: the details are
+// only used in the jsonl-v2 output format, where they are named fields that
+// add to (or replace) the ones that every event carries
 fn format_output_parsable(
     context: &'static str,
     name: &str,
@@ -280,7 +1285,11 @@ fn format_output_parsable(
     operation: &str,
     arg1: &str,
     arg2: &str,
+    details: serde_json::Value,
 ) -> String {
+    if OUTPUT_FORMAT.get() == Some(&OutputFormat::JsonlV2) {
+        return format_output_event(context, name, code, operation, details);
+    }
     let mresult = format_err_parsable(code);
     let mtype = if code == 0 {
         String::from("INFO")
@@ -322,24 +1331,87 @@ fn format_output_parsable(
     .to_string()
 }
 
-/// Extract the configuration from a TOML file, given the file name and the
-/// pertaining arguments as resulting from the command line. A description of
+// helper to format an event in the jsonl-v2 output format: every event has
+// all the common fields, that are null when they do not apply, and in the
+// main context the name refers to the subject of the message (for instance
+// a configuration file) instead of a job
+fn format_output_event(
+    context: &'static str,
+    name: &str,
+    code: u64,
+    operation: &str,
+    details: serde_json::Value,
+) -> String {
+    let name = if name.is_empty() { None } else { Some(name) };
+    let (job, subject) = if context == CONTEXT_MAIN {
+        (None, name)
+    } else {
+        (name, None)
+    };
+
+    // timestamps are in RFC 3339 format, with milliseconds, using the same
+    // offset from UTC as the time of the run
+    let now = OffsetDateTime::now_utc().to_offset(RUN_TIMESTAMP.offset());
+    let (hours, minutes, _) = now.offset().as_hms();
+    let timestamp = format!(
+        "{}.{:03}{}{:02}:{:02}",
+        format_timestamp(&now, "%Y-%m-%dT%H:%M:%S"),
+        now.millisecond(),
+        if now.offset().is_negative() { '-' } else { '+' },
+        hours.abs(),
+        minutes.abs(),
+    );
+
+    let mut event = json!({
+        "schema_version": OUTPUT_SCHEMA_VERSION,
+        "timestamp": timestamp,
+        "context": context,
+        "message_type": if code == 0 { "INFO" } else { "ERROR" },
+        "operation": operation,
+        "code": code,
+        "result": format_err_parsable(code),
+        "reason": if code == 0 { None } else { Some(format_err_verbose(code)) },
+        "job": job,
+        "subject": subject,
+        "source": null,
+        "destination": null,
+        "bytes": null,
+        "duration_ms": null,
+        "message": null
+    });
+    if let (Some(fields), serde_json::Value::Object(details)) = (event.as_object_mut(), details) {
+        fields.extend(details);
+    }
+    event.to_string()
+}
+
+/// Extract the configuration from a TOML file, along with the files that it
+/// includes, given the file name and the pertaining arguments as resulting
+/// from the command line. A description of
 /// the arguments follows:
 ///
 ///     config_file: the path to the configuration file (CLI argument)
-///     verbose: turn verbosity on (goes into config), negation of CLI argument 'quiet'
+///     verbosity: level of detail of output (goes into config), CLI arguments 'quiet' and 'verbose'
 ///     parsable_output: produce machine readable output, CLI argument 'parsable-output'
+///     select_jobs: jobs to run instead of active ones, CLI argument 'job'
+///     all_jobs: run all defined jobs instead of active ones, CLI argument 'all-jobs'
+///     exclude_jobs: jobs not to run even if active, CLI argument 'exclude-job'
+///     set_variables: local variables overriding definitions, CLI argument 'set'
 ///
 /// Returns a tuple consisting in a global configuration and a list of job
 /// configurations if successful, otherwise an error containing a string that
 /// briefly describes the error and possibly where it occurred.
 ///
-/// As internal functions it also includes utilities for string replacement
-/// in the handled paths.
+/// As internal functions it also includes utilities to read and merge the
+/// included files, and to build error messages.
 fn extract_config(
-    config_file: &PathBuf,
-    verbose: bool,
+    config_file: &Path,
+    verbosity: Verbosity,
     parsable_output: bool,
+    select_jobs: &[String],
+    all_jobs: bool,
+    exclude_jobs: &[String],
+    set_variables: &[(String, String)],
 ) -> std::io::Result<(CopyJobGlobalConfig, Vec<CopyJobConfig>)> {
     // local helpers:
 
@@ -351,76 +1423,157 @@ fn extract_config(
         )
     }
 
-    // l2. handle environment and local variables
-    fn _ec_replace_variables_in_string(
-        pattern: &Regex,
-        format: &str,
-        source: &str,
-        vars: &HashMap<String, String>,
-    ) -> String {
-        let mut result = String::from(source);
-        // mimick shell by replacing undefined variables with the empty string:
-        // since the same function is used for both local and environment vars,
-        // this represents a difference with the Python version, that considered
-        // mentioning an undefined local variable a fatal error
-        // WARNING: this actually assumes that the regular expression pattern
-        //          "[%$]\{[a-zA-Z_][a-zA-Z0-9_]*\}" cannot appear in the source or
-        //          the destination directory within job definitions
-        while let Some(caps) = pattern.captures(result.as_str()) {
-            let varname = caps.get(1).map_or("", |m| m.as_str());
-            let occurrence = format.replace("*", varname);
-            if let Some(replacement) = vars.get(varname) {
-                result = result.replace(&occurrence, replacement);
-            } else {
-                result = result.replace(&occurrence, "");
-            }
-        }
-        result
+    // l1a. create a specific error for patterns that cannot be compiled
+    fn _ec_error_invalid_pattern(job: &str, key: &str, pattern: &str, msg: &str) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{}:{job}:{key}:'{pattern}' {msg}",
+                format_err_parsable(ERR_INVALID_PATTERN)
+            )
+            .as_str(),
+        )
     }
 
-    // l3. handle special path markers
-    fn _ec_replace_markers_in_string(
-        source: &str,
-        user_home: &Path,
-        config_file_dir: &Path,
-    ) -> String {
-        let mut result = String::from(source);
-        for (mkey, mlist) in DIR_MARKERS.clone().iter() {
-            for marker in mlist {
-                if result.starts_with(marker) {
-                    match *mkey {
-                        "USER-HOME" => {
-                            result = user_home.to_string_lossy().to_string()
-                                + &String::from(&result[1..]); // to preserve the slash
-                        }
-                        "CONFIG-FILE-DIR" => {
-                            result = config_file_dir.to_string_lossy().to_string()
-                                + &String::from(&result[1..]); // to preserve the slash
-                        }
-                        _ => {}
-                    }
+    // l1b. create a specific error for mentions of undefined variables
+    fn _ec_error_undefined_variable(location: &str, var: &str, msg: &str) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{}:{location}:{var}: {msg}",
+                format_err_parsable(ERR_UNDEFINED_VARIABLE)
+            )
+            .as_str(),
+        )
+    }
+
+    // l2. read a configuration file along with the files it includes, which
+    // are merged first and in the order in which they are listed, so that
+    // the including file can override their values; the chain of including
+    // files is used to detect cycles, and files that have already been read
+    // are not read again; errors in included files mention the file path,
+    // and the path of the file where each job is defined is returned along
+    // with the merged table, in the same order as the jobs
+    fn _ec_read_config_file(
+        config_file: &Path,
+        overrides: &HashMap<String, String>,
+        including: &mut Vec<PathBuf>,
+        loaded: &mut Vec<PathBuf>,
+    ) -> std::io::Result<(toml::Table, Vec<PathBuf>)> {
+        let mut table: toml::Table = match toml::from_str(fs::read_to_string(config_file)?.as_str())
+        {
+            Ok(toml_table) => toml_table,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format_err_parsable(ERR_INVALID_CONFIG_FILE),
+                ));
+            }
+        };
+        loaded.push(config_file.to_path_buf());
+        let key_prefix = if including.is_empty() {
+            String::new()
+        } else {
+            format!("{}:", config_file.display())
+        };
+        for key in table.keys() {
+            if !CONFIG_GLOBAL_KEYS.contains_key(key.as_str()) {
+                return Err(_ec_error_invalid_config(&format!("{key_prefix}{key}")));
+            }
+        }
+        let job_files = vec![
+            config_file.to_path_buf();
+            table
+                .get("job")
+                .and_then(|v| v.as_array())
+                .map_or(0, |jobs| jobs.len())
+        ];
+        let Some(includes) = table.remove("include") else {
+            return Ok((table, job_files));
+        };
+        let specs: Vec<&str> = includes
+            .as_array()
+            .and_then(|items| items.iter().map(|item| item.as_str()).collect())
+            .ok_or_else(|| _ec_error_invalid_config(&format!("{key_prefix}include")))?;
+        let mut variables: HashMap<String, String> = HashMap::new();
+        if let Some(toml::Value::Table(items)) = table.get("variables") {
+            for (name, value) in items.iter() {
+                if let Some(value) = value.as_str() {
+                    variables.insert(name.clone(), String::from(value));
                 }
             }
         }
-        result
+        variables.extend(overrides.clone());
+        let strict = table
+            .get("strict_variables")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        including.push(config_file.to_path_buf());
+        let mut merged = toml::Table::new();
+        let mut merged_job_files: Vec<PathBuf> = Vec::new();
+        for spec in specs {
+            let path = include_file_path(spec, config_file, &variables, strict).map_err(
+                |(var, msg)| {
+                    _ec_error_undefined_variable(&format!("{key_prefix}include"), &var, &msg)
+                },
+            )?;
+            let path = path.canonicalize().map_err(|_| {
+                _ec_error_invalid_config(&format!("{key_prefix}include:{}", path.display()))
+            })?;
+            if let Some(start) = including.iter().position(|p| *p == path) {
+                let mut cycle: Vec<String> = including[start..]
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect();
+                cycle.push(path.display().to_string());
+                return Err(_ec_error_invalid_config(&format!(
+                    "include:{}",
+                    cycle.join("->")
+                )));
+            }
+            if loaded.contains(&path) {
+                continue;
+            }
+            let (included, included_job_files) =
+                _ec_read_config_file(&path, overrides, including, loaded).map_err(|e| {
+                    if e.kind() == std::io::ErrorKind::InvalidInput {
+                        e
+                    } else {
+                        _ec_error_invalid_config(&format!("{key_prefix}include:{}", path.display()))
+                    }
+                })?;
+            _ec_merge_config_tables(&mut merged, included);
+            merged_job_files.extend(included_job_files);
+        }
+        including.pop();
+        _ec_merge_config_tables(&mut merged, table);
+        merged_job_files.extend(job_files);
+        Ok((merged, merged_job_files))
     }
 
-    // l4. normalize path slashes (forward+back & multiple)
-    fn _ec_normalize_path_slashes(path: &str) -> String {
-        if cfg!(windows) {
-            Regex::new("\\[\\]+")
-                .unwrap() // cannot panic for we know the RE is correct
-                .replace_all(&path.replace("/", "\\"), "\\")
-                .to_string()
-        } else {
-            Regex::new("/[/]+")
-                .unwrap() // cannot panic for we know the RE is correct
-                .replace_all(&path.replace("\\", "/"), "/")
-                .to_string()
+    // l3. merge a configuration table into another one: jobs and templates
+    // are appended, local variables are merged and other values replaced
+    fn _ec_merge_config_tables(base: &mut toml::Table, overlay: toml::Table) {
+        for (key, value) in overlay {
+            match (base.get_mut(&key), value) {
+                (Some(toml::Value::Array(items)), toml::Value::Array(more))
+                    if key == "job" || key == "template" =>
+                {
+                    items.extend(more);
+                }
+                (Some(toml::Value::Table(items)), toml::Value::Table(more))
+                    if key == "variables" =>
+                {
+                    items.extend(more);
+                }
+                (_, value) => {
+                    base.insert(key, value);
+                }
+            }
         }
     }
 
-    // l5. add trailing slashes
+    // l4. add trailing slashes
     fn _ec_add_trailing_slashes(path: &str) -> String {
         if cfg!(windows) {
             if path.ends_with("\\") || path.ends_with("/") {
@@ -454,36 +1607,47 @@ fn extract_config(
         trash_on_delete: true,
         trash_on_overwrite: false,
         halt_on_errors: false,
+        dry_run: false,
+        preserve_mtime: false,
+        preserve_atime: false,
+        preserve_permissions: true,
+        preserve_owner: false,
+        atomic_copy: false,
+        verify_after_copy: false,
+        verify_retries: 0,
+        hash_algorithm: HashAlgorithm::Sha256,
+        parallelism: 1,
+        max_parallel_jobs: 1,
+        hash_cache: None,
+        log_file: None,
+        log_max_size: 0,
+        log_level: None,
+        lock_file: None,
 
         // the following parameters are defined through CLI arguments only
-        config_file: PathBuf::from(_ec_normalize_path_slashes(&String::from(
+        config_file: PathBuf::from(normalize_path_slashes(&String::from(
             config_file.as_os_str().to_str().unwrap(),
         ))),
-        verbose,
+        verbosity,
         parsable_output,
     };
     let mut job_configs: Vec<CopyJobConfig> = Vec::new();
+    let mut job_prefixes: Vec<String> = Vec::new();
     let mut check_active_jobs: Vec<String> = Vec::new();
-    let allowed_globals = vec![
-        "active_jobs",
-        "variables",
-        "recursive",
-        "case_sensitive",
-        "follow_symlinks",
-        "overwrite",
-        "skip_newer",
-        "check_content",
-        "remove_others_matching",
-        "create_directories",
-        "keep_structure",
-        "trash_on_delete",
-        "trash_on_overwrite",
-        "halt_on_errors",
-        "job",
-    ];
 
-    let config_map = match toml::from_str(fs::read_to_string(config_file)?.as_str()) {
-        Ok(toml_text) => CfgMap::from_toml(toml_text),
+    let set_variables: HashMap<String, String> = set_variables.iter().cloned().collect();
+    let (mut config_table, job_files) = _ec_read_config_file(
+        config_file,
+        &set_variables,
+        &mut Vec::new(),
+        &mut Vec::new(),
+    )?;
+
+    // templates are resolved first, so that job definitions are complete
+    // when they are read below
+    resolve_templates(&mut config_table).map_err(|key| _ec_error_invalid_config(&key))?;
+    let config_map = match toml::Value::Table(config_table).try_into() {
+        Ok(toml_value) => CfgMap::from_toml(toml_value),
         _ => {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -494,22 +1658,16 @@ fn extract_config(
 
     // check that global keys are all known: if not report offending key
     for key in config_map.keys() {
-        if !allowed_globals.contains(&key.as_str()) {
+        if !CONFIG_GLOBAL_KEYS.contains_key(key.as_str()) {
             return Err(_ec_error_invalid_config(key));
         }
     }
 
     // strings that will be used to build actal paths
     let var_user_home = home_dir().unwrap();
-    let var_config_file_dir = PathBuf::from(config_file.clone().parent().unwrap());
+    let var_config_file_dir = PathBuf::from(config_file.parent().unwrap());
 
-    let mut sys_variables: HashMap<String, String> = HashMap::new();
-    for (var, value) in env::vars_os() {
-        sys_variables.insert(
-            String::from(var.to_str().unwrap()),
-            String::from(value.to_str().unwrap()),
-        );
-    }
+    let sys_variables = environment_variables();
 
     // collect globals:
 
@@ -561,6 +1719,25 @@ fn extract_config(
         }
     }
 
+    // local variables also include placeholders for the time of the run, and
+    // are overridden by the ones set on the command line
+    let mut path_variables = variables_with_timestamps(&global_config.variables, &RUN_TIMESTAMP);
+    path_variables.extend(set_variables.clone());
+
+    // 2a. strictness about undefined variables (only at global level)
+    let mut strict_variables = false;
+    let cur_key = "strict_variables";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            strict_variables = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
     // 3. recursive flag
     let cur_key = "recursive";
     let cur_item = config_map.get(cur_key);
@@ -717,6 +1894,249 @@ fn extract_config(
         None => { /* OK to go, default already set */ }
     }
 
+    // 15. only report operations without performing them
+    let cur_key = "dry_run";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.dry_run = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 16. preserve modification time
+    let cur_key = "preserve_mtime";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.preserve_mtime = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 17. preserve access time
+    let cur_key = "preserve_atime";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.preserve_atime = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 18. preserve permissions
+    let cur_key = "preserve_permissions";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.preserve_permissions = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 19. preserve owner and group
+    let cur_key = "preserve_owner";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.preserve_owner = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 20. copy to temporary file, then rename
+    let cur_key = "atomic_copy";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.atomic_copy = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 21. compare contents after copying
+    let cur_key = "verify_after_copy";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_bool() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.verify_after_copy = *item.as_bool().unwrap();
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 22. copy attempts after failed verification
+    let cur_key = "verify_retries";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_int() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.verify_retries = u32::try_from(*item.as_int().unwrap())
+                .map_err(|_| _ec_error_invalid_config(cur_key))?;
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 23. algorithm used to compare contents
+    let cur_key = "hash_algorithm";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_str() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.hash_algorithm = HashAlgorithm::from_name(item.as_str().unwrap())
+                .ok_or_else(|| _ec_error_invalid_config(cur_key))?;
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 24. file where digests are cached (only at global level)
+    let cur_key = "hash_cache";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_str() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            let s = expand_path_value(
+                item.as_str().unwrap(),
+                &path_variables,
+                &sys_variables,
+                strict_variables,
+                &var_user_home,
+                &var_config_file_dir,
+            )
+            .map_err(|(var, msg)| _ec_error_undefined_variable(cur_key, &var, &msg))?;
+            global_config.hash_cache = Some(PathBuf::from(normalize_path_slashes(&s)));
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 25. number of files copied concurrently (0: one per available CPU)
+    let cur_key = "parallelism";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_int() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.parallelism = parallelism_from_value(*item.as_int().unwrap())
+                .ok_or_else(|| _ec_error_invalid_config(cur_key))?;
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 26. number of jobs run concurrently (only at global level)
+    let cur_key = "max_parallel_jobs";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_int() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.max_parallel_jobs = parallelism_from_value(*item.as_int().unwrap())
+                .ok_or_else(|| _ec_error_invalid_config(cur_key))?;
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 27. file where messages are logged (only at global level)
+    let cur_key = "log_file";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_str() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            let s = expand_path_value(
+                item.as_str().unwrap(),
+                &path_variables,
+                &sys_variables,
+                strict_variables,
+                &var_user_home,
+                &var_config_file_dir,
+            )
+            .map_err(|(var, msg)| _ec_error_undefined_variable(cur_key, &var, &msg))?;
+            global_config.log_file = Some(PathBuf::from(normalize_path_slashes(&s)));
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 28. size that causes the log file to be rotated (only at global level)
+    let cur_key = "log_max_size";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_int() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.log_max_size = u64::try_from(*item.as_int().unwrap())
+                .map_err(|_| _ec_error_invalid_config(cur_key))?;
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 29. level of detail of messages (only at global level)
+    let cur_key = "log_level";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_str() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            global_config.log_level = Some(
+                Verbosity::from_name(item.as_str().unwrap())
+                    .ok_or_else(|| _ec_error_invalid_config(cur_key))?,
+            );
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
+    // 30. file locked while running jobs (only at global level)
+    let cur_key = "lock_file";
+    let cur_item = config_map.get(cur_key);
+    match cur_item {
+        Some(item) => {
+            if !item.is_str() {
+                return Err(_ec_error_invalid_config(cur_key));
+            }
+            let s = expand_path_value(
+                item.as_str().unwrap(),
+                &path_variables,
+                &sys_variables,
+                strict_variables,
+                &var_user_home,
+                &var_config_file_dir,
+            )
+            .map_err(|(var, msg)| _ec_error_undefined_variable(cur_key, &var, &msg))?;
+            global_config.lock_file = Some(PathBuf::from(normalize_path_slashes(&s)));
+        }
+        None => { /* OK to go, default already set */ }
+    }
+
     // collect job definitions
     // note that specific job flags are directly taken from the corresponding
     // global configuration values, so filling will not be needed later; jobs
@@ -730,9 +2150,26 @@ fn extract_config(
             if !c.is_list() {
                 return Err(_ec_error_invalid_config(cur_key));
             }
-            for elem in c.as_list().unwrap_or(&Vec::<CfgValue>::new()).iter() {
+            for (index, elem) in c
+                .as_list()
+                .unwrap_or(&Vec::<CfgValue>::new())
+                .iter()
+                .enumerate()
+            {
+                // errors in jobs defined in included files mention the path
+                // of the file, and @/ refers to the directory of that file
+                let job_file = job_files.get(index).map_or(config_file, |p| p.as_path());
+                let job_prefix = if job_file == config_file {
+                    String::new()
+                } else {
+                    format!("{}:", job_file.display())
+                };
+                let job_error = |key: &str| _ec_error_invalid_config(&format!("{job_prefix}{key}"));
+                let job_config_file_dir = job_file
+                    .parent()
+                    .map_or(var_config_file_dir.clone(), PathBuf::from);
                 if !elem.is_map() {
-                    return Err(_ec_error_invalid_config(cur_key));
+                    return Err(job_error(cur_key));
                 } else {
                     let mut job = CopyJobConfig {
                         job_name: String::new(),
@@ -753,7 +2190,86 @@ fn extract_config(
                         trash_on_delete: global_config.trash_on_delete,
                         trash_on_overwrite: global_config.trash_on_overwrite,
                         halt_on_errors: global_config.halt_on_errors,
+                        dry_run: global_config.dry_run,
+                        preserve_mtime: global_config.preserve_mtime,
+                        preserve_atime: global_config.preserve_atime,
+                        preserve_permissions: global_config.preserve_permissions,
+                        preserve_owner: global_config.preserve_owner,
+                        atomic_copy: global_config.atomic_copy,
+                        verify_after_copy: global_config.verify_after_copy,
+                        verify_retries: global_config.verify_retries,
+                        hash_algorithm: global_config.hash_algorithm,
+                        parallelism: global_config.parallelism,
+                        depends_on: Vec::new(),
+                    };
+                    // patterns and variable mentions are checked while reading
+                    // them, but errors are reported after the job name is known
+                    let mut invalid_pattern: Option<(&str, String, String)> = None;
+                    let mut undefined_variable: Option<(&str, String, String)> = None;
+                    // local variables defined in the job are read first, as
+                    // they are needed in paths: they override the global ones
+                    // and are overridden by the ones set on the command line
+                    let mut job_variables = path_variables.clone();
+                    if let Some(item) = elem.as_map().unwrap().get("variables") {
+                        let cur_key = "job/variables";
+                        if !item.is_map() {
+                            return Err(job_error(cur_key));
+                        }
+                        for (key, value) in item.as_map().unwrap().iter() {
+                            if !value.is_str() {
+                                return Err(job_error(cur_key));
+                            }
+                            job_variables.insert(
+                                String::from(key.as_str()),
+                                String::from(value.as_str().unwrap()),
+                            );
+                        }
+                        job_variables.extend(set_variables.clone());
+                    }
+                    // pattern lists are all read the same way, and each one
+                    // is combined into a single pattern
+                    let mut read_patterns = |cur_key: &'static str, item: &CfgValue| {
+                        if !item.is_list() {
+                            return Err(job_error(cur_key));
+                        }
+                        let mut li: Vec<String> = Vec::new();
+                        for i in item.as_list().unwrap() {
+                            if let Some(s) = i.as_str() {
+                                if !s.is_empty() {
+                                    if let Err(e) = Regex::new(s) {
+                                        invalid_pattern.get_or_insert((
+                                            cur_key,
+                                            String::from(s),
+                                            regexp_error_message(&e),
+                                        ));
+                                    }
+                                    li.push(String::from(s));
+                                }
+                            }
+                        }
+                        Ok(combine_regexp_patterns(&li))
                     };
+                    for (key, cur_key, pattern) in [
+                        (
+                            "patterns_include",
+                            "job/patterns_include",
+                            &mut job.include_pattern,
+                        ),
+                        (
+                            "patterns_exclude",
+                            "job/patterns_exclude",
+                            &mut job.exclude_pattern,
+                        ),
+                        (
+                            "patterns_exclude_dir",
+                            "job/patterns_exclude_dir",
+                            &mut job.excludedir_pattern,
+                        ),
+                    ] {
+                        if let Some(item) = elem.as_map().unwrap().get(key) {
+                            *pattern = read_patterns(cur_key, item)?;
+                        }
+                    }
                     for (key, item) in elem.as_map().unwrap().iter() {
                         // a note on variable and marker replacements: first we
                         // replace local variables, because they could mention
@@ -765,206 +2281,258 @@ fn extract_config(
                             "name" => {
                                 let cur_key = "job/name";
                                 if !item.is_str() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.job_name = String::from(item.as_str().unwrap());
                                 if !RE_JOBNAME.is_match(&job.job_name) {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                             }
                             "source" => {
                                 let cur_key = "job/source";
                                 if !item.is_str() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
-                                let mut s = String::from(item.as_str().unwrap());
-                                s = _ec_replace_variables_in_string(
-                                    &RE_VARMENTION_LOC,
-                                    &FMT_VARMENTION_LOC,
-                                    &s,
-                                    &global_config.variables,
-                                );
-                                s = _ec_replace_variables_in_string(
-                                    &RE_VARMENTION_ENV,
-                                    &FMT_VARMENTION_ENV,
-                                    &s,
+                                let s = match expand_path_value(
+                                    item.as_str().unwrap(),
+                                    &job_variables,
                                     &sys_variables,
-                                );
-                                s = _ec_replace_markers_in_string(
-                                    &s,
+                                    strict_variables,
                                     &var_user_home,
-                                    &var_config_file_dir,
-                                );
+                                    &job_config_file_dir,
+                                ) {
+                                    Ok(expanded) => expanded,
+                                    Err((var, msg)) => {
+                                        undefined_variable.get_or_insert((cur_key, var, msg));
+                                        String::from(item.as_str().unwrap())
+                                    }
+                                };
                                 job.source_dir = PathBuf::from(_ec_add_trailing_slashes(
-                                    &_ec_normalize_path_slashes(&s),
+                                    &normalize_path_slashes(&s),
                                 ));
                             }
                             "destination" => {
                                 let cur_key = "job/destination";
                                 if !item.is_str() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
-                                let mut s = String::from(item.as_str().unwrap());
-                                s = _ec_replace_variables_in_string(
-                                    &RE_VARMENTION_LOC,
-                                    &FMT_VARMENTION_LOC,
-                                    &s,
-                                    &global_config.variables,
-                                );
-                                s = _ec_replace_variables_in_string(
-                                    &RE_VARMENTION_ENV,
-                                    &FMT_VARMENTION_ENV,
-                                    &s,
+                                let s = match expand_path_value(
+                                    item.as_str().unwrap(),
+                                    &job_variables,
                                     &sys_variables,
-                                );
-                                s = _ec_replace_markers_in_string(
-                                    &s,
+                                    strict_variables,
                                     &var_user_home,
-                                    &var_config_file_dir,
-                                );
+                                    &job_config_file_dir,
+                                ) {
+                                    Ok(expanded) => expanded,
+                                    Err((var, msg)) => {
+                                        undefined_variable.get_or_insert((cur_key, var, msg));
+                                        String::from(item.as_str().unwrap())
+                                    }
+                                };
                                 job.destination_dir = PathBuf::from(_ec_add_trailing_slashes(
-                                    &_ec_normalize_path_slashes(&s),
+                                    &normalize_path_slashes(&s),
                                 ));
                             }
-                            "patterns_include" => {
-                                let cur_key = "job/patterns_include";
-                                if !item.is_list() {
-                                    return Err(_ec_error_invalid_config(cur_key));
-                                }
-                                let mut li: Vec<String> = Vec::new();
-                                for i in item.as_list().unwrap() {
-                                    if let Some(s) = i.as_str() {
-                                        if !s.is_empty() {
-                                            li.push(String::from(s));
-                                        }
-                                    }
-                                }
-                                job.include_pattern = combine_regexp_patterns(&li);
-                            }
-                            "patterns_exclude" => {
-                                let cur_key = "job/patterns_exclude";
-                                if !item.is_list() {
-                                    return Err(_ec_error_invalid_config(cur_key));
-                                }
-                                let mut li: Vec<String> = Vec::new();
-                                for i in item.as_list().unwrap() {
-                                    if let Some(s) = i.as_str() {
-                                        if !s.is_empty() {
-                                            li.push(String::from(s));
-                                        }
-                                    }
-                                }
-                                job.exclude_pattern = combine_regexp_patterns(&li);
-                            }
-                            "patterns_exclude_dir" => {
-                                let cur_key = "job/patterns_exclude_dir";
-                                if !item.is_list() {
-                                    return Err(_ec_error_invalid_config(cur_key));
-                                }
-                                let mut li: Vec<String> = Vec::new();
-                                for i in item.as_list().unwrap() {
-                                    if let Some(s) = i.as_str() {
-                                        if !s.is_empty() {
-                                            li.push(String::from(s));
-                                        }
-                                    }
-                                }
-                                job.excludedir_pattern = combine_regexp_patterns(&li);
+                            "patterns_include" | "patterns_exclude" | "patterns_exclude_dir" => { /* already read */
                             }
                             "recursive" => {
                                 let cur_key = "job/recursive";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.recursive = *item.as_bool().unwrap();
                             }
                             "case_sensitive" => {
                                 let cur_key = "job/case_sensitive";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.case_sensitive = *item.as_bool().unwrap();
                             }
                             "follow_symlinks" => {
                                 let cur_key = "job/follow_symlinks";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.follow_symlinks = *item.as_bool().unwrap();
                             }
                             "overwrite" => {
                                 let cur_key = "job/overwrite";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.overwrite = *item.as_bool().unwrap();
                             }
                             "skip_newer" => {
                                 let cur_key = "job/skip_newer";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.skip_newer = *item.as_bool().unwrap();
                             }
                             "check_content" => {
                                 let cur_key = "job/check_content";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.check_content = *item.as_bool().unwrap();
                             }
                             "remove_others_matching" => {
                                 let cur_key = "job/remove_others_matching";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.remove_others_matching = *item.as_bool().unwrap();
                             }
                             "create_directories" => {
                                 let cur_key = "job/create_directories";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.create_directories = *item.as_bool().unwrap();
                             }
                             "keep_structure" => {
                                 let cur_key = "job/keep_structure";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.keep_structure = *item.as_bool().unwrap();
                             }
                             "trash_on_delete" => {
                                 let cur_key = "job/halt_on_errors";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.trash_on_delete = *item.as_bool().unwrap();
                             }
                             "trash_on_overwrite" => {
                                 let cur_key = "job/halt_on_errors";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.trash_on_overwrite = *item.as_bool().unwrap();
                             }
                             "halt_on_errors" => {
                                 let cur_key = "job/halt_on_errors";
                                 if !item.is_bool() {
-                                    return Err(_ec_error_invalid_config(cur_key));
+                                    return Err(job_error(cur_key));
                                 }
                                 job.halt_on_errors = *item.as_bool().unwrap();
                             }
+                            "dry_run" => {
+                                let cur_key = "job/dry_run";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.dry_run = *item.as_bool().unwrap();
+                            }
+                            "preserve_mtime" => {
+                                let cur_key = "job/preserve_mtime";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.preserve_mtime = *item.as_bool().unwrap();
+                            }
+                            "preserve_atime" => {
+                                let cur_key = "job/preserve_atime";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.preserve_atime = *item.as_bool().unwrap();
+                            }
+                            "preserve_permissions" => {
+                                let cur_key = "job/preserve_permissions";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.preserve_permissions = *item.as_bool().unwrap();
+                            }
+                            "preserve_owner" => {
+                                let cur_key = "job/preserve_owner";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.preserve_owner = *item.as_bool().unwrap();
+                            }
+                            "atomic_copy" => {
+                                let cur_key = "job/atomic_copy";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.atomic_copy = *item.as_bool().unwrap();
+                            }
+                            "verify_after_copy" => {
+                                let cur_key = "job/verify_after_copy";
+                                if !item.is_bool() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.verify_after_copy = *item.as_bool().unwrap();
+                            }
+                            "verify_retries" => {
+                                let cur_key = "job/verify_retries";
+                                if !item.is_int() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.verify_retries = u32::try_from(*item.as_int().unwrap())
+                                    .map_err(|_| job_error(cur_key))?;
+                            }
+                            "parallelism" => {
+                                let cur_key = "job/parallelism";
+                                if !item.is_int() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.parallelism = parallelism_from_value(*item.as_int().unwrap())
+                                    .ok_or_else(|| job_error(cur_key))?;
+                            }
+                            "variables" => { /* already read */ }
+                            "depends_on" => {
+                                let cur_key = "job/depends_on";
+                                if !item.is_list() {
+                                    return Err(job_error(cur_key));
+                                }
+                                for i in item.as_list().unwrap() {
+                                    match i.as_str() {
+                                        Some(s) => job.depends_on.push(String::from(s)),
+                                        None => return Err(job_error(cur_key)),
+                                    }
+                                }
+                            }
+                            "hash_algorithm" => {
+                                let cur_key = "job/hash_algorithm";
+                                if !item.is_str() {
+                                    return Err(job_error(cur_key));
+                                }
+                                job.hash_algorithm =
+                                    HashAlgorithm::from_name(item.as_str().unwrap())
+                                        .ok_or_else(|| job_error(cur_key))?;
+                            }
                             _ => {
-                                return Err(_ec_error_invalid_config(cur_key));
+                                return Err(job_error(cur_key));
                             }
                         }
                     }
                     if job.job_name.is_empty() {
-                        return Err(_ec_error_invalid_config(cur_key));
+                        return Err(job_error(cur_key));
+                    }
+                    if let Some((key, pattern, msg)) = invalid_pattern {
+                        return Err(_ec_error_invalid_pattern(
+                            &format!("{job_prefix}{}", job.job_name),
+                            key,
+                            &pattern,
+                            &msg,
+                        ));
+                    }
+                    if let Some((key, var, msg)) = undefined_variable {
+                        return Err(_ec_error_undefined_variable(
+                            &format!("{job_prefix}{}:{key}", job.job_name),
+                            &var,
+                            &msg,
+                        ));
                     }
                     global_config.job_list.push(String::from(&job.job_name));
                     job_configs.push(job);
+                    job_prefixes.push(job_prefix);
                 }
             }
         }
@@ -979,10 +2547,727 @@ fn extract_config(
         }
     }
 
+    // check that jobs only depend on defined jobs, and that dependencies are
+    // not circular
+    let cur_key = "job/depends_on";
+    for (job, job_prefix) in job_configs.iter().zip(job_prefixes.iter()) {
+        for item in job.depends_on.iter() {
+            if !global_config.job_list.contains(item) {
+                return Err(_ec_error_invalid_config(&format!(
+                    "{job_prefix}{cur_key}:{item}"
+                )));
+            }
+        }
+    }
+    let dependencies: Vec<Vec<String>> = job_configs
+        .iter()
+        .map(|job| job.depends_on.clone())
+        .collect();
+    if let Err(cycle) = dependency_order(&global_config.job_list, &dependencies) {
+        return Err(_ec_error_invalid_config(&format!(
+            "{cur_key}:{}",
+            cycle.join("->")
+        )));
+    }
+
+    // jobs selected on the command line replace or filter the active jobs:
+    // mentioning a job that is not defined is an error just as it would be
+    // if the job were listed in the configuration file
+    for item in select_jobs {
+        if !global_config.job_list.contains(item) {
+            return Err(_ec_error_invalid_config(&format!("--job={item}")));
+        }
+    }
+    for item in exclude_jobs {
+        if !global_config.job_list.contains(item) {
+            return Err(_ec_error_invalid_config(&format!("--exclude-job={item}")));
+        }
+    }
+    if all_jobs {
+        global_config.active_jobs = global_config.job_list.clone();
+    } else if !select_jobs.is_empty() {
+        global_config.active_jobs = select_jobs.to_vec();
+    }
+    global_config
+        .active_jobs
+        .retain(|item| !exclude_jobs.contains(item));
+
     // now the configuration is complete (unless this function panicked)
     Ok((global_config, job_configs))
 }
 
+/// Check a TOML configuration file without stopping at the first error, and
+/// collect all the problems that are found. The following checks are made:
+///
+///     - TOML syntax errors
+///     - unknown keys, both at global level and within job definitions
+///     - values of the wrong type
+///     - invalid job, template and variable names, and missing mandatory
+///       job keys (also considering the ones inherited from templates)
+///     - undefined templates, and circular inheritance among templates
+///     - regular expressions in job patterns that cannot be compiled
+///     - active jobs that are not defined
+///     - mentions of undefined local variables in source and destination
+///     - dependencies on jobs that are not defined, and circular dependencies
+///     - included files that cannot be read, and circular inclusions
+///
+/// Included files are checked as well, and definitions found in any of them
+/// are considered when checking the others, along with the local variables
+/// set on the command line. Returns the list of problems,
+/// sorted by file and by position in the file, which is empty if the whole
+/// configuration is valid; an error is only returned if the main file cannot
+/// be read.
+///
+/// As internal functions it also includes utilities to locate problems and
+/// to check value types.
+fn validate_config(
+    config_file: &Path,
+    set_variables: &[(String, String)],
+) -> std::io::Result<Vec<ConfigProblem>> {
+    // local helpers:
+
+    // l1. build a problem, converting the start of a span into line/column
+    fn _vc_problem(
+        source: &ConfigSource,
+        span: &std::ops::Range<usize>,
+        key: &str,
+        message: &str,
+    ) -> ConfigProblem {
+        let before = &source.text[..span.start.min(source.text.len())];
+        ConfigProblem {
+            file: source.path.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before.chars().rev().take_while(|c| *c != '\n').count() + 1,
+            key: String::from(key),
+            message: String::from(message),
+        }
+    }
+
+    // l2. check that a value is of the expected type
+    fn _vc_check_type(value: &DeValue, expected: ConfigValueType) -> bool {
+        match expected {
+            ConfigValueType::Boolean => value.is_bool(),
+            ConfigValueType::Integer => value
+                .as_integer()
+                .is_some_and(|i| !i.as_str().starts_with('-')),
+            ConfigValueType::String => value.is_str(),
+            ConfigValueType::StringList => value
+                .as_array()
+                .is_some_and(|a| a.iter().all(|i| i.get_ref().is_str())),
+            ConfigValueType::StringMap => value
+                .as_table()
+                .is_some_and(|t| t.values().all(|v| v.get_ref().is_str())),
+            ConfigValueType::TableList => value
+                .as_array()
+                .is_some_and(|a| a.iter().all(|i| i.get_ref().is_table())),
+        }
+    }
+
+    // l3. describe an expected type
+    fn _vc_type_name(expected: ConfigValueType) -> &'static str {
+        match expected {
+            ConfigValueType::Boolean => "a boolean",
+            ConfigValueType::Integer => "a non negative integer",
+            ConfigValueType::String => "a string",
+            ConfigValueType::StringList => "a list of strings",
+            ConfigValueType::StringMap => "a table of strings",
+            ConfigValueType::TableList => "an array of tables",
+        }
+    }
+
+    // l4. collect a configuration file and, recursively, the files that it
+    // includes: problems concerning inclusion are reported at the position
+    // of the offending item, and files already collected are not read again
+    fn _vc_collect_sources(
+        source: ConfigSource,
+        overrides: &HashMap<String, String>,
+        including: &mut Vec<PathBuf>,
+        sources: &mut Vec<ConfigSource>,
+        problems: &mut Vec<ConfigProblem>,
+    ) {
+        sources.push(source.clone());
+        let (document, _) = DeTable::parse_recoverable(&source.text);
+        let document = document.get_ref();
+        let Some(items) = document.get("include").and_then(|v| v.get_ref().as_array()) else {
+            return;
+        };
+        let mut variables: HashMap<String, String> = HashMap::new();
+        if let Some(table) = document
+            .get("variables")
+            .and_then(|v| v.get_ref().as_table())
+        {
+            for (key, value) in table.iter() {
+                if let Some(value) = value.get_ref().as_str() {
+                    variables.insert(key.get_ref().to_string(), String::from(value));
+                }
+            }
+        }
+        variables.extend(overrides.clone());
+        let strict = document
+            .get("strict_variables")
+            .and_then(|v| v.get_ref().as_bool())
+            .unwrap_or(false);
+        including.push(source.path.clone());
+        for item in items.iter() {
+            let Some(spec) = item.get_ref().as_str() else {
+                continue; // already reported as a type error
+            };
+            let path = match include_file_path(spec, &source.path, &variables, strict) {
+                Ok(path) => path,
+                Err((var, msg)) => {
+                    problems.push(_vc_problem(
+                        &source,
+                        &item.span(),
+                        "include",
+                        &format!("variable '{var}': {msg}"),
+                    ));
+                    continue;
+                }
+            };
+            let Ok(path) = path.canonicalize() else {
+                problems.push(_vc_problem(
+                    &source,
+                    &item.span(),
+                    "include",
+                    &format!("included file '{spec}' not found"),
+                ));
+                continue;
+            };
+            if let Some(start) = including.iter().position(|p| *p == path) {
+                let cycle: Vec<String> = including[start..]
+                    .iter()
+                    .chain(std::iter::once(&path))
+                    .map(|p| p.display().to_string())
+                    .collect();
+                problems.push(_vc_problem(
+                    &source,
+                    &item.span(),
+                    "include",
+                    &format!("circular inclusion: {}", cycle.join(" -> ")),
+                ));
+                continue;
+            }
+            if sources.iter().any(|s| s.path == path) {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    _vc_collect_sources(
+                        ConfigSource { path, text },
+                        overrides,
+                        including,
+                        sources,
+                        problems,
+                    );
+                }
+                Err(err) => {
+                    problems.push(_vc_problem(
+                        &source,
+                        &item.span(),
+                        "include",
+                        &format!("cannot read included file '{}': {err}", path.display()),
+                    ));
+                }
+            }
+        }
+        including.pop();
+    }
+
+    // a configuration file along with its contents
+    #[derive(Clone)]
+    struct ConfigSource {
+        path: PathBuf,
+        text: String,
+    }
+
+    let text = fs::read_to_string(config_file)?;
+    let mut problems: Vec<ConfigProblem> = Vec::new();
+    let mut sources: Vec<ConfigSource> = Vec::new();
+    let overrides: HashMap<String, String> = set_variables.iter().cloned().collect();
+    _vc_collect_sources(
+        ConfigSource {
+            path: config_file.to_path_buf(),
+            text,
+        },
+        &overrides,
+        &mut Vec::new(),
+        &mut sources,
+        &mut problems,
+    );
+
+    // the parser recovers from syntax errors, so that the remaining part of
+    // each document can still be checked
+    let mut documents = Vec::new();
+    for source in sources.iter() {
+        let (document, errors) = DeTable::parse_recoverable(&source.text);
+        for err in errors {
+            problems.push(_vc_problem(
+                source,
+                &err.span().unwrap_or(0..0),
+                "",
+                err.message(),
+            ));
+        }
+        documents.push(document);
+    }
+
+    // check global keys and types
+    for (source, document) in sources.iter().zip(documents.iter()) {
+        let document = document.get_ref();
+        for (key, value) in document.iter() {
+            match CONFIG_GLOBAL_KEYS.get(key.get_ref().as_ref()) {
+                Some(expected) => {
+                    if !_vc_check_type(value.get_ref(), *expected) {
+                        problems.push(_vc_problem(
+                            source,
+                            &value.span(),
+                            key.get_ref(),
+                            &format!("value must be {}", _vc_type_name(*expected)),
+                        ));
+                    }
+                }
+                None => {
+                    problems.push(_vc_problem(
+                        source,
+                        &key.span(),
+                        key.get_ref(),
+                        "unknown key",
+                    ));
+                }
+            }
+        }
+
+        // check values that must be chosen among a set of names
+        if let Some(value) = document.get("hash_algorithm") {
+            let name = value.get_ref().as_str().unwrap_or_default();
+            if value.get_ref().is_str() && HashAlgorithm::from_name(name).is_none() {
+                problems.push(_vc_problem(
+                    source,
+                    &value.span(),
+                    "hash_algorithm",
+                    &format!("unknown hash algorithm '{name}'"),
+                ));
+            }
+        }
+        if let Some(value) = document.get("log_level") {
+            let name = value.get_ref().as_str().unwrap_or_default();
+            if value.get_ref().is_str() && Verbosity::from_name(name).is_none() {
+                problems.push(_vc_problem(
+                    source,
+                    &value.span(),
+                    "log_level",
+                    &format!("unknown log level '{name}'"),
+                ));
+            }
+        }
+    }
+
+    // collect local variable names, which are shared among all files, and
+    // include the placeholders for the time of the run and the variables set
+    // on the command line
+    let mut variables: Vec<String> = TIMESTAMP_VARIABLES
+        .iter()
+        .map(|(name, _)| String::from(*name))
+        .chain(overrides.keys().cloned())
+        .collect();
+    for (source, document) in sources.iter().zip(documents.iter()) {
+        if let Some(table) = document
+            .get_ref()
+            .get("variables")
+            .and_then(|v| v.get_ref().as_table())
+        {
+            for (key, _) in table.iter() {
+                if !RE_VARNAME.is_match(key.get_ref()) {
+                    problems.push(_vc_problem(
+                        source,
+                        &key.span(),
+                        &format!("variables/{}", key.get_ref()),
+                        "invalid variable name",
+                    ));
+                }
+                variables.push(key.get_ref().to_string());
+            }
+        }
+    }
+
+    // check template and job definitions: templates come first, so that all
+    // template names are known when checking what jobs inherit from them
+    struct Definition {
+        file: usize,
+        span: std::ops::Range<usize>,
+        name: Option<String>,
+        keys: Vec<String>,
+        extends: Option<(std::ops::Range<usize>, String)>,
+        depends_on: Option<(std::ops::Range<usize>, Vec<String>)>,
+        variables: Vec<String>,
+        mentions: Vec<(std::ops::Range<usize>, String, String, String)>,
+    }
+    let mut templates: Vec<Definition> = Vec::new();
+    let mut jobs: Vec<Definition> = Vec::new();
+    for section in ["template", "job"] {
+        for (file, (source, document)) in sources.iter().zip(documents.iter()).enumerate() {
+            let Some(elems) = document
+                .get_ref()
+                .get(section)
+                .and_then(|v| v.get_ref().as_array())
+            else {
+                continue;
+            };
+            for elem in elems.iter() {
+                let Some(table) = elem.get_ref().as_table() else {
+                    continue; // already reported as a type error
+                };
+                let mut definition = Definition {
+                    file,
+                    span: elem.span(),
+                    name: None,
+                    keys: Vec::new(),
+                    extends: None,
+                    depends_on: None,
+                    variables: Vec::new(),
+                    mentions: Vec::new(),
+                };
+                for (key, value) in table.iter() {
+                    let cur_key = format!("{section}/{}", key.get_ref());
+                    let Some(expected) = CONFIG_JOB_KEYS.get(key.get_ref().as_ref()) else {
+                        problems.push(_vc_problem(source, &key.span(), &cur_key, "unknown key"));
+                        continue;
+                    };
+                    // appended patterns count as the patterns they extend
+                    definition.keys.push(String::from(
+                        key.get_ref()
+                            .strip_suffix("_append")
+                            .unwrap_or(key.get_ref()),
+                    ));
+                    if !_vc_check_type(value.get_ref(), *expected) {
+                        problems.push(_vc_problem(
+                            source,
+                            &value.span(),
+                            &cur_key,
+                            &format!("value must be {}", _vc_type_name(*expected)),
+                        ));
+                        continue;
+                    }
+                    match key.get_ref().as_ref() {
+                        "name" => {
+                            let name = value.get_ref().as_str().unwrap_or_default();
+                            if !RE_JOBNAME.is_match(name) {
+                                problems.push(_vc_problem(
+                                    source,
+                                    &value.span(),
+                                    &cur_key,
+                                    &format!("invalid {section} name '{name}'"),
+                                ));
+                            }
+                            definition.name = Some(String::from(name));
+                        }
+                        "extends" => {
+                            let name = value.get_ref().as_str().unwrap_or_default();
+                            definition.extends = Some((value.span(), String::from(name)));
+                        }
+                        "depends_on" => {
+                            let mut dependencies = Vec::new();
+                            for item in value.get_ref().as_array().unwrap().iter() {
+                                let name = item.get_ref().as_str().unwrap_or_default();
+                                dependencies.push(String::from(name));
+                            }
+                            definition.depends_on = Some((value.span(), dependencies));
+                        }
+                        "hash_algorithm" => {
+                            let name = value.get_ref().as_str().unwrap_or_default();
+                            if HashAlgorithm::from_name(name).is_none() {
+                                problems.push(_vc_problem(
+                                    source,
+                                    &value.span(),
+                                    &cur_key,
+                                    &format!("unknown hash algorithm '{name}'"),
+                                ));
+                            }
+                        }
+                        "variables" => {
+                            for (name, _) in value.get_ref().as_table().unwrap().iter() {
+                                if !RE_VARNAME.is_match(name.get_ref()) {
+                                    problems.push(_vc_problem(
+                                        source,
+                                        &name.span(),
+                                        &format!("{cur_key}/{}", name.get_ref()),
+                                        "invalid variable name",
+                                    ));
+                                }
+                                definition.variables.push(name.get_ref().to_string());
+                            }
+                        }
+                        "source" | "destination" => {
+                            // mentions that provide a default value are always
+                            // accepted, the other ones are checked when all the
+                            // variables available to jobs are known
+                            let path = value.get_ref().as_str().unwrap_or_default();
+                            for caps in RE_VARMENTION_LOC.captures_iter(path) {
+                                let varname = caps.get(1).map_or("", |m| m.as_str());
+                                let operator = caps.get(2).map_or("", |m| m.as_str());
+                                let argument = caps.get(3).map_or("", |m| m.as_str());
+                                if operator != "-" {
+                                    definition.mentions.push((
+                                        value.span(),
+                                        cur_key.clone(),
+                                        String::from(varname),
+                                        if operator == "?" && !argument.is_empty() {
+                                            format!("undefined variable '{varname}': {argument}")
+                                        } else {
+                                            format!("undefined variable '{varname}'")
+                                        },
+                                    ));
+                                }
+                            }
+                        }
+                        "patterns_include"
+                        | "patterns_exclude"
+                        | "patterns_exclude_dir"
+                        | "patterns_include_append"
+                        | "patterns_exclude_append"
+                        | "patterns_exclude_dir_append" => {
+                            for pattern in value.get_ref().as_array().unwrap().iter() {
+                                let re = pattern.get_ref().as_str().unwrap_or_default();
+                                if let Err(err) = Regex::new(re) {
+                                    problems.push(_vc_problem(
+                                        source,
+                                        &pattern.span(),
+                                        &cur_key,
+                                        &format!(
+                                            "invalid pattern '{re}': {}",
+                                            regexp_error_message(&err)
+                                        ),
+                                    ));
+                                }
+                            }
+                        }
+                        _ => { /* type already checked */ }
+                    }
+                }
+                if !table.contains_key("name") {
+                    problems.push(_vc_problem(
+                        source,
+                        &elem.span(),
+                        &format!("{section}/name"),
+                        "missing mandatory key",
+                    ));
+                }
+                if section == "template" {
+                    templates.push(definition);
+                } else {
+                    jobs.push(definition);
+                }
+            }
+        }
+    }
+
+    // check that templates exist and that inheritance does not loop
+    let mut extends: HashMap<String, Option<String>> = HashMap::new();
+    for template in templates.iter() {
+        if let Some(name) = &template.name {
+            extends.insert(name.clone(), template.extends.as_ref().map(|e| e.1.clone()));
+        }
+    }
+    for (section, definitions) in [("template", &templates), ("job", &jobs)] {
+        for definition in definitions.iter() {
+            if let Some((span, parent)) = &definition.extends {
+                if !extends.contains_key(parent) {
+                    problems.push(_vc_problem(
+                        &sources[definition.file],
+                        span,
+                        &format!("{section}/extends"),
+                        &format!("undefined template '{parent}'"),
+                    ));
+                }
+            }
+        }
+    }
+    for template in templates.iter() {
+        let (Some(name), Some((span, _))) = (&template.name, &template.extends) else {
+            continue;
+        };
+        if let Err(cycle) = template_chain(name, &extends) {
+            if cycle[0] == *name {
+                problems.push(_vc_problem(
+                    &sources[template.file],
+                    span,
+                    "template/extends",
+                    &format!("circular inheritance: {}", cycle.join(" -> ")),
+                ));
+            }
+        }
+    }
+
+    // jobs must define the mandatory keys, or inherit them from templates
+    // (except for the name); the same applies to dependencies, which are
+    // recorded for every named job so that they can be checked when all
+    // job names are known
+    let mut job_names: Vec<String> = Vec::new();
+    let mut job_dependencies: Vec<(usize, std::ops::Range<usize>, Vec<String>)> = Vec::new();
+    for job in jobs.iter() {
+        // when the chain of templates cannot be followed, the problem has
+        // already been reported and inherited keys cannot be known
+        let chain: Option<Vec<&Definition>> = match &job.extends {
+            Some((_, parent)) if !extends.contains_key(parent) => None,
+            Some((_, parent)) => template_chain(parent, &extends).ok().map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| templates.iter().find(|t| t.name.as_ref() == Some(name)))
+                    .collect()
+            }),
+            None => Some(Vec::new()),
+        };
+        if let Some(chain) = &chain {
+            for mandatory in CONFIG_JOB_MANDATORY_KEYS.iter() {
+                if *mandatory != "name"
+                    && !job.keys.iter().any(|k| k == mandatory)
+                    && !chain.iter().any(|t| t.keys.iter().any(|k| k == mandatory))
+                {
+                    problems.push(_vc_problem(
+                        &sources[job.file],
+                        &job.span,
+                        &format!("job/{mandatory}"),
+                        "missing mandatory key",
+                    ));
+                }
+            }
+        }
+        // variables mentioned in the job and in its templates can be defined
+        // globally, in the job itself or in any of its templates
+        let layers: Vec<&Definition> = std::iter::once(job)
+            .chain(chain.clone().unwrap_or_default())
+            .collect();
+        for layer in layers.iter() {
+            for (span, key, varname, message) in layer.mentions.iter() {
+                if !variables.contains(varname)
+                    && !layers.iter().any(|d| d.variables.contains(varname))
+                {
+                    problems.push(_vc_problem(&sources[layer.file], span, key, message));
+                }
+            }
+        }
+        if let Some(name) = &job.name {
+            job_names.push(name.clone());
+            let depends_on = std::iter::once(job)
+                .chain(chain.unwrap_or_default())
+                .find_map(|d| {
+                    d.depends_on
+                        .clone()
+                        .map(|(span, deps)| (d.file, span, deps))
+                });
+            job_dependencies.push(depends_on.unwrap_or((job.file, job.span.clone(), Vec::new())));
+        }
+    }
+
+    // check that jobs depend on defined jobs, and that there are no cycles
+    for (file, span, dependencies) in job_dependencies.iter() {
+        for name in dependencies.iter() {
+            if !job_names.iter().any(|j| j == name) {
+                problems.push(_vc_problem(
+                    &sources[*file],
+                    span,
+                    "job/depends_on",
+                    &format!("undefined job '{name}'"),
+                ));
+            }
+        }
+    }
+    let dependencies: Vec<Vec<String>> = job_dependencies
+        .iter()
+        .map(|(_, _, deps)| deps.clone())
+        .collect();
+    if let Err(cycle) = dependency_order(&job_names, &dependencies) {
+        let index = job_names.iter().position(|j| *j == cycle[0]).unwrap();
+        let (file, span, _) = &job_dependencies[index];
+        problems.push(_vc_problem(
+            &sources[*file],
+            span,
+            "job/depends_on",
+            &format!("circular dependency: {}", cycle.join(" -> ")),
+        ));
+    }
+
+    // check that active jobs are defined
+    for (source, document) in sources.iter().zip(documents.iter()) {
+        let Some(active_jobs) = document
+            .get_ref()
+            .get("active_jobs")
+            .and_then(|v| v.get_ref().as_array())
+        else {
+            continue;
+        };
+        for item in active_jobs.iter() {
+            if let Some(name) = item.get_ref().as_str() {
+                if !job_names.iter().any(|j| j == name) {
+                    problems.push(_vc_problem(
+                        source,
+                        &item.span(),
+                        "active_jobs",
+                        &format!("undefined job '{name}'"),
+                    ));
+                }
+            }
+        }
+    }
+
+    // the same problem in a template may be found through several jobs
+    problems.sort_by_cached_key(|p| {
+        let file = sources.iter().position(|s| s.path == p.file);
+        (file, p.line, p.column, p.key.clone(), p.message.clone())
+    });
+    problems.dedup();
+    Ok(problems)
+}
+
+/// Find the configuration files in a directory and its subdirectories whose
+/// names match a pattern, where `*` stands for any sequence of characters and
+/// `?` for a single character. A description of the arguments follows:
+///
+///     scan_dir: the directory to be scanned
+///     pattern: the pattern that configuration file names must match
+///
+/// Returns the canonicalized paths of the files that were found, sorted by
+/// path, along with the errors on entries that cannot be read within the
+/// directory, which are skipped; an error is returned if the directory itself
+/// cannot be read. Symbolic links to directories are not followed.
+fn find_config_files(
+    scan_dir: &Path,
+    pattern: &str,
+) -> std::io::Result<(Vec<PathBuf>, Vec<walkdir::Error>)> {
+    let mut expr = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => expr.push_str(".*"),
+            '?' => expr.push('.'),
+            _ => expr.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expr.push('$');
+    let name_match = Regex::new(&expr).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, regexp_error_message(&e))
+    })?;
+
+    let mut result: Vec<PathBuf> = Vec::new();
+    let mut errors: Vec<walkdir::Error> = Vec::new();
+    for entry in WalkDir::new(scan_dir).follow_links(false) {
+        match entry {
+            Ok(entry) => {
+                if !entry.file_type().is_dir()
+                    && entry.path().is_file()
+                    && name_match.is_match(&entry.file_name().to_string_lossy())
+                {
+                    let path = entry.into_path();
+                    result.push(path.canonicalize().unwrap_or(path));
+                }
+            }
+            Err(e) if e.depth() == 0 => return Err(e.into()),
+            Err(e) => errors.push(e),
+        }
+    }
+    result.sort();
+    Ok((result, errors))
+}
+
 /// Build a list of files in a directory matching/unmatching a pattern by
 /// either listing the files in that directory or traversing it recursively.
 /// A description of the accepted parameters follows:
@@ -1007,17 +3292,16 @@ fn list_files_matching(
     recursive: bool,
     follow_symlinks: bool,
     case_sensitive: bool,
-) -> Option<Vec<PathBuf>> {
-    // FIXME: for now erratic patterns only cause a no-match (acceptable?)
-    //        in the release version they should actually return None
+) -> Result<Vec<PathBuf>, regex::Error> {
+    // patterns are checked when the configuration is read, so an erratic
+    // pattern here is unexpected: in this case the error is returned instead
+    // of a list, as matching nothing would silently disable exclusions
     let include_match = RegexBuilder::new(format!("^{include_pattern}$").as_str())
         .case_insensitive(!case_sensitive)
-        .build()
-        .unwrap_or(RE_MATCH_NO_FILE.clone());
+        .build()?;
     let exclude_match = RegexBuilder::new(format!("^{exclude_pattern}$").as_str())
         .case_insensitive(!case_sensitive)
-        .build()
-        .unwrap_or(RE_MATCH_NO_FILE.clone());
+        .build()?;
 
     // excluded directory is not matched as ^$, to also ignore subdirectories
     // of the excluded directory (this solution is working for now); since the
@@ -1027,8 +3311,7 @@ fn list_files_matching(
     let psre = format!("\\{ps}");
     let excludedir_match = RegexBuilder::new(format!("{psre}{excludedir_pattern}{psre}").as_str())
         .case_insensitive(!case_sensitive)
-        .build()
-        .unwrap_or(RE_MATCH_NO_FILE.clone());
+        .build()?;
 
     let depth: usize = if recursive { usize::MAX } else { 1 };
     let mut result: Vec<PathBuf> = Vec::new();
@@ -1073,7 +3356,197 @@ fn list_files_matching(
             }
         }
     }
-    Some(result)
+    Ok(result)
+}
+
+// helper to copy the contents of a file into a newly created (or truncated)
+// destination, which keeps its default (or existing) permissions; when sync
+// is set the destination is flushed to the storage device through the same
+// handle used for writing, before any permission is applied to it
+fn copy_file_contents(source: &Path, destination: &Path, sync: bool) -> std::io::Result<u64> {
+    let mut input = File::open(source)?;
+    let mut output = File::create(destination)?;
+    let copied = std::io::copy(&mut input, &mut output)?;
+    if sync {
+        output.sync_all()?;
+    }
+    Ok(copied)
+}
+
+// helper to copy the contents of a file in chunks, in the same way as above,
+// reporting the number of bytes written after each chunk
+fn copy_file_contents_chunked(
+    source: &Path,
+    destination: &Path,
+    sync: bool,
+    on_bytes: &dyn Fn(u64),
+) -> std::io::Result<u64> {
+    use std::io::Write;
+    let mut input = File::open(source)?;
+    let mut output = File::create(destination)?;
+    let mut buffer = vec![0; COPY_CHUNK_SIZE];
+    let mut copied: u64 = 0;
+    loop {
+        let size = input.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        output.write_all(&buffer[..size])?;
+        copied += size as u64;
+        on_bytes(size as u64);
+    }
+    if sync {
+        output.sync_all()?;
+    }
+    Ok(copied)
+}
+
+// helper to build the path of the temporary file used for atomic copies, a
+// hidden file in the same directory as the destination
+fn temporary_file_path(destination: &Path) -> PathBuf {
+    let file_name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    destination.with_file_name(format!(".{file_name}{TEMPORARY_FILE_SUFFIX}"))
+}
+
+// helper to apply the source metadata to a copied file according to the
+// preserve_* flags of a job: the owner is set first, as changing it might
+// reset some permission bits on UNIX; owner is only handled on UNIX
+fn apply_file_metadata(s_stat: &fs::Metadata, destination: &Path, job: &CopyJobConfig) -> Outcome {
+    #[cfg(unix)]
+    if job.preserve_owner {
+        use std::os::unix::fs::MetadataExt;
+        if std::os::unix::fs::chown(destination, Some(s_stat.uid()), Some(s_stat.gid())).is_err() {
+            return Outcome::Error(FOERR_CANNOT_SET_OWNER);
+        }
+    }
+    if job.preserve_permissions && fs::set_permissions(destination, s_stat.permissions()).is_err() {
+        return Outcome::Error(FOERR_CANNOT_SET_PERMISSIONS);
+    }
+    if job.preserve_mtime
+        && set_file_mtime(destination, FileTime::from_last_modification_time(s_stat)).is_err()
+    {
+        return Outcome::Error(FOERR_CANNOT_SET_TIMES);
+    }
+    if job.preserve_atime
+        && set_file_atime(destination, FileTime::from_last_access_time(s_stat)).is_err()
+    {
+        return Outcome::Error(FOERR_CANNOT_SET_TIMES);
+    }
+    Outcome::Success
+}
+
+// helper to verify that the contents of a copied file match the source: the
+// cache is bypassed here, as the point is reading back the contents that
+// have actually been written
+fn verify_copied_file(source_path: &Path, target_path: &Path, job: &CopyJobConfig) -> Outcome {
+    match same_file_contents(source_path, target_path, job.hash_algorithm, None) {
+        Ok(true) => Outcome::Success,
+        Ok(false) => Outcome::Error(FOERR_VERIFICATION_FAILED),
+        Err(_) => Outcome::Error(FOERR_CANNOT_VERIFY),
+    }
+}
+
+// helper to actually write the destination file once all the checks have
+// been performed, possibly through a temporary file (atomic copy), to verify
+// the copy when requested and to apply the source metadata to the copied
+// file as configured in the job: a copy that cannot be verified is removed
+// only when it is a temporary file, otherwise it is left in place, as it
+// could still be the only copy of the contents of the source
+fn write_destination_file(
+    source_path: &Path,
+    destination_path: &Path,
+    s_stat: &fs::Metadata,
+    overwriting: bool,
+    job: &CopyJobConfig,
+    on_bytes: Option<&dyn Fn(u64)>,
+) -> Outcome {
+    // try to send the file to garbage bin if configured to do so
+    // and if we are actually overwriting the destination file with
+    // no opposing condition (file age, contents, accessibility, etc);
+    // for atomic copies this is done right before replacing the file
+    if overwriting && job.trash_on_overwrite && !job.atomic_copy {
+        let _ = trash::delete(destination_path);
+    }
+
+    // for atomic copies the file is first written to a temporary file
+    // in the destination directory, that replaces the destination only
+    // when complete, so that an interrupted copy never leaves behind
+    // a truncated destination file
+    let target_path = if job.atomic_copy {
+        temporary_file_path(destination_path)
+    } else {
+        destination_path.to_path_buf()
+    };
+
+    // actually copy the file using OS API: since the OS copy also
+    // applies the source permissions, when these are not to be kept
+    // only the contents are copied into a newly created file; when
+    // progress is reported, the copy is atomic or it has to be verified
+    // the file is copied by hand instead, so that the file can be flushed
+    // and copied again while it is still writable, and permissions are
+    // anyway applied below if they are to be kept
+    let res = match on_bytes {
+        Some(on_bytes) => {
+            copy_file_contents_chunked(source_path, &target_path, job.atomic_copy, on_bytes)
+        }
+        None if job.preserve_permissions && !job.atomic_copy && !job.verify_after_copy => {
+            fs::copy(source_path, &target_path)
+        }
+        None => copy_file_contents(source_path, &target_path, job.atomic_copy),
+    };
+    match res {
+        Ok(_) => {
+            // the copy is verified before the source metadata is applied:
+            // a temporary file that does not match the source is removed,
+            // while a destination written in place is left as it is, and
+            // can be overwritten by a further attempt
+            if job.verify_after_copy {
+                if let Outcome::Error(code) = verify_copied_file(source_path, &target_path, job) {
+                    if job.atomic_copy {
+                        let _ = fs::remove_file(&target_path);
+                    }
+                    return Outcome::Error(code);
+                }
+            }
+            // FileOpOutcome::Success is returned only here, after an
+            // actually successful copy operation, unless the source
+            // metadata cannot be applied to the destination: in this
+            // case the copied contents are kept (and, for atomic copies,
+            // moved in place) and the metadata error is reported
+            let outcome = apply_file_metadata(s_stat, &target_path, job);
+            if !job.atomic_copy {
+                return outcome;
+            }
+            if overwriting && job.trash_on_overwrite {
+                let _ = trash::delete(destination_path);
+            }
+            match fs::rename(&target_path, destination_path) {
+                Ok(_) => outcome,
+                Err(res_err) => {
+                    let _ = fs::remove_file(&target_path);
+                    if res_err.kind() == std::io::ErrorKind::PermissionDenied {
+                        Outcome::Error(FOERR_DESTINATION_IS_READONLY)
+                    } else {
+                        Outcome::Error(FOERR_GENERIC_FAILURE)
+                    }
+                }
+            }
+        }
+        Err(res_err) => {
+            if job.atomic_copy {
+                let _ = fs::remove_file(&target_path);
+            }
+            if res_err.kind() == std::io::ErrorKind::PermissionDenied {
+                Outcome::Error(FOERR_DESTINATION_IS_READONLY)
+            } else {
+                Outcome::Error(FOERR_GENERIC_FAILURE)
+            }
+        }
+    }
 }
 
 /// Attempt to copy a single file to a destination (provided as a path):
@@ -1087,21 +3560,33 @@ fn list_files_matching(
 ///
 ///     source: the full specification of source file
 ///     destination: the full specification of destination file
+///     job: &CopyJobConfig, the job whose flags drive the operation
+///     cache: optional persistent cache of file digests
+///     on_bytes: optional function called with the number of bytes written
+///               after each chunk, to report progress
+///
+/// the following job flags are taken into account:
+///
 ///     overwrite: if false, never overwrite an existing destination
 ///     skip_newer: if overwrite, only overwrite when source is newer
 ///     check_content: if overwrite, only overwrite when contents differ
 ///     follow_symlinks: follow symbolic links
 ///     create_directories: create directory if it does not exist yet
 ///     trash_on_overwrite: to send to garbage bin instead of overwriting
+///     dry_run: perform all checks, but do not modify the file system
+///     preserve_*: apply source times, permissions and owner to destination
+///     atomic_copy: write to a temporary file, then rename it to destination
+///     verify_after_copy: compare contents of destination and source
+///     verify_retries: how many times to copy again if verification fails
+///
+/// when dry_run is set the returned outcome is the one that the actual copy
+/// would most likely produce, as only the final file operations are skipped
 fn copy_file(
     source: &Path,
     destination: &Path,
-    overwrite: bool,
-    skip_newer: bool,
-    check_content: bool,
-    follow_symlinks: bool,
-    create_directories: bool,
-    trash_on_overwrite: bool,
+    job: &CopyJobConfig,
+    cache: Option<&HashCache>,
+    on_bytes: Option<&dyn Fn(u64)>,
 ) -> Outcome {
     // normalize paths
     let source_path = PathBuf::from(&source.canonicalize().unwrap_or_default());
@@ -1127,7 +3612,7 @@ fn copy_file(
             if s_stat.is_dir() {
                 return Outcome::Error(FOERR_SOURCE_IS_DIR);
             }
-            if s_stat.is_symlink() && !follow_symlinks {
+            if s_stat.is_symlink() && !job.follow_symlinks {
                 // TODO: is it expected?
                 return Outcome::Error(FOERR_SOURCE_IS_SYMLINK);
             }
@@ -1136,14 +3621,14 @@ fn copy_file(
                     // if we are here, then the destination exists: check
                     // whether overwrite is false, compare s_stat, d_stat and
                     // possibly hashes
-                    if !overwrite {
+                    if !job.overwrite {
                         return Outcome::Error(FOERR_DESTINATION_EXISTS);
                     } else if d_stat.is_dir() {
                         return Outcome::Error(FOERR_DESTINATION_IS_DIR);
-                    } else if d_stat.is_symlink() && !follow_symlinks {
+                    } else if d_stat.is_symlink() && !job.follow_symlinks {
                         return Outcome::Error(FOERR_DESTINATION_IS_SYMLINK);
                     }
-                    if skip_newer {
+                    if job.skip_newer {
                         match s_stat.modified() {
                             Ok(s_mtime) => match d_stat.modified() {
                                 Ok(d_mtime) => {
@@ -1161,21 +3646,23 @@ fn copy_file(
                             }
                         }
                     }
-                    // only when asked perform content checking via SHA256
-                    // and skip copy if the contents are the same
-                    if check_content {
-                        match sha256_digest(&source_path) {
-                            Ok(source_hash) => match sha256_digest(&destination_path) {
-                                Ok(destination_hash) => {
-                                    if destination_hash == source_hash {
-                                        return Outcome::Error(FOERR_DESTINATION_IS_IDENTICAL);
-                                    }
-                                }
-                                Err(_) => {
-                                    return Outcome::Error(FOERR_DESTINATION_NOT_ACCESSIBLE);
-                                }
-                            },
-                            Err(_) => {
+                    // only when asked perform content checking via the job
+                    // hash algorithm and skip copy if the contents are the same
+                    if job.check_content {
+                        match same_file_contents(
+                            &source_path,
+                            &destination_path,
+                            job.hash_algorithm,
+                            cache,
+                        ) {
+                            Ok(true) => {
+                                return Outcome::Error(FOERR_DESTINATION_IS_IDENTICAL);
+                            }
+                            Ok(false) => { /* contents differ: go on */ }
+                            Err(Side::Destination) => {
+                                return Outcome::Error(FOERR_DESTINATION_NOT_ACCESSIBLE);
+                            }
+                            Err(Side::Source) => {
                                 return Outcome::Error(FOERR_SOURCE_NOT_ACCESSIBLE);
                             }
                         }
@@ -1205,10 +3692,10 @@ fn copy_file(
                             // missing directories are to be created, only bail
                             // out on directory creation errors; otherwise it
                             // is safe to go on without further checks
-                            if !create_directories {
+                            if !job.create_directories {
                                 return Outcome::Error(FOERR_CANNOT_CREATE_DIR);
                             }
-                            if create_dir_all(&destination_dir).is_err() {
+                            if !job.dry_run && create_dir_all(&destination_dir).is_err() {
                                 return Outcome::Error(FOERR_CANNOT_CREATE_DIR);
                             }
                         }
@@ -1216,26 +3703,40 @@ fn copy_file(
                 }
             }
 
-            // try to send the file to garbage bin if configured to do so
-            // and if we are actually overwriting the destination file with
-            // no opposing condition (file age, contents, accessibility, etc)
-            if overwriting && trash_on_overwrite {
-                let _ = trash::delete(&destination_path);
+            // in dry run mode all checks have been passed at this point, so
+            // the copy operation is considered successful
+            if job.dry_run {
+                return Outcome::Success;
             }
 
-            // actually copy the file using OS API
-            // let res = fs::copy(&source_path, &destination_path);
-            match fs::copy(&source_path, &destination_path) {
-                Ok(_) => {
-                    // FileOpOutcome::Success is returned only here, after an
-                    // actually successful copy operation
-                    Outcome::Success
-                }
-                Err(res_err) => {
-                    if res_err.kind() == std::io::ErrorKind::PermissionDenied {
-                        Outcome::Error(FOERR_DESTINATION_IS_READONLY)
-                    } else {
-                        Outcome::Error(FOERR_GENERIC_FAILURE)
+            // write the destination file, and when requested verify that
+            // its contents match the source, retrying the copy as many times
+            // as configured: a bad copy is either a temporary file that has
+            // already been removed, or a destination that is overwritten in
+            // place, so the retries never send it to trash
+            let mut attempts: u32 = 0;
+            loop {
+                let outcome = write_destination_file(
+                    &source_path,
+                    &destination_path,
+                    &s_stat,
+                    overwriting,
+                    job,
+                    on_bytes,
+                );
+                match outcome {
+                    Outcome::Error(FOERR_VERIFICATION_FAILED) if attempts < job.verify_retries => {
+                        attempts += 1;
+                        overwriting = overwriting && job.atomic_copy;
+                    }
+                    Outcome::Success => {
+                        if let Some(cache) = cache {
+                            cache.store_copy(&source_path, &destination_path, job.hash_algorithm);
+                        }
+                        return outcome;
+                    }
+                    _ => {
+                        return outcome;
                     }
                 }
             }
@@ -1252,7 +3753,13 @@ fn copy_file(
 ///     destination: the full specification of destination file
 ///     follow_symlinks: follow symbolic links
 ///     trash_on_delete: to send to garbage bin instead of deleting
-fn remove_file(destination: &Path, follow_symlinks: bool, trash_on_delete: bool) -> Outcome {
+///     dry_run: perform all checks, but do not modify the file system
+fn remove_file(
+    destination: &Path,
+    follow_symlinks: bool,
+    trash_on_delete: bool,
+    dry_run: bool,
+) -> Outcome {
     // normalize paths
     let destination_path = destination.canonicalize().unwrap_or_default();
 
@@ -1263,6 +3770,8 @@ fn remove_file(destination: &Path, follow_symlinks: bool, trash_on_delete: bool)
                 Outcome::Error(FOERR_DESTINATION_IS_DIR)
             } else if d_stat.is_symlink() && !follow_symlinks {
                 Outcome::Error(FOERR_DESTINATION_IS_SYMLINK)
+            } else if dry_run {
+                Outcome::Success
             } else if trash_on_delete {
                 if trash::delete(&destination_path).is_err() {
                     if fs::remove_file(destination_path).is_ok() {
@@ -1285,38 +3794,576 @@ fn remove_file(destination: &Path, follow_symlinks: bool, trash_on_delete: bool)
     }
 }
 
-/// Perform a single copy job, by building a list of files to copy and by
-/// copying them if possible using `copyfile` seen above. To be noticed that:
-///
-///     1) the job name has to be provided for reporting/logging purposes
-///     2) source_dir and destination_dir need to be passed to this function
-///        *after* replacing shortcuts and variables with their values, as no
-///        substitution is performed here
-///
-/// A description of the parameters follows:
-///
-///     job: &CopyJobConfig, containing all the job parameters
-///     verbose: bool, provide output while running the job
-///     parsable_output: bool, provide machine readable output if verbose
-///
-/// NOTE: writes to stdout/stderr
-/// NOTE: machine readable prefix of this section is JOB
+/// Remove the temporary files left behind by atomic copies that have been
+/// interrupted, and return the list of files that have actually been
+/// removed: only the temporary files for the provided destination files are
+/// considered, so that files that might belong to other jobs or tools are
+/// never touched. A full description of the required parameters follows:
 ///
-/// As internal functions it also includes simple formatters for writing
-/// suitable messages when needed.
-fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) -> Outcome {
-    // local helpers:
+///     destinations: the full specification of the destination files
+fn remove_temporary_files<'a>(destinations: impl Iterator<Item = &'a PathBuf>) -> Vec<PathBuf> {
+    let mut result: Vec<PathBuf> = Vec::new();
 
-    // l1. format a message (both machine readable and verbose output)
-    fn _format_message_rsj(
-        parsable_output: bool,
+    for destination in destinations {
+        let temporary_path = temporary_file_path(destination);
+        if fs::symlink_metadata(&temporary_path).is_ok_and(|m| m.is_file())
+            && fs::remove_file(&temporary_path).is_ok()
+        {
+            result.push(temporary_path);
+        }
+    }
+    result
+}
+
+// Statistics about the files processed by a job, to be written to the run
+// report: copies that are skipped because the destination is newer, is
+// identical to the source or simply exists are not counted as failures
+#[derive(Default)]
+struct JobStats {
+    considered: usize,
+    copied: usize,
+    skipped_newer: usize,
+    skipped_identical: usize,
+    skipped_exists: usize,
+    removed: usize,
+    failed: BTreeMap<u64, usize>,
+    failed_paths: Vec<(PathBuf, u64)>,
+    bytes_copied: u64,
+    dry_run: bool,
+    duration: Duration,
+}
+
+impl JobStats {
+    // record the outcome of an operation on a file, given the size of the
+    // file in case of successful copies
+    fn record(&mut self, path: &Path, code: u64, copied_bytes: Option<u64>) {
+        match code {
+            ERR_OK => match copied_bytes {
+                Some(bytes) => {
+                    self.copied += 1;
+                    self.bytes_copied += bytes;
+                }
+                None => self.removed += 1,
+            },
+            FOERR_DESTINATION_IS_NEWER => self.skipped_newer += 1,
+            FOERR_DESTINATION_IS_IDENTICAL => self.skipped_identical += 1,
+            FOERR_DESTINATION_EXISTS => self.skipped_exists += 1,
+            _ => {
+                *self.failed.entry(code).or_insert(0) += 1;
+                self.failed_paths.push((path.to_path_buf(), code));
+            }
+        }
+    }
+
+    // build the report of a job, given its name and result code
+    fn report(&self, name: &str, code: u64) -> serde_json::Value {
+        let failed: serde_json::Map<String, serde_json::Value> = self
+            .failed
+            .iter()
+            .map(|(code, count)| (format_err_parsable(*code), json!(count)))
+            .collect();
+        let failed_paths: Vec<serde_json::Value> = self
+            .failed_paths
+            .iter()
+            .map(|(path, code)| {
+                json!({
+                    "path": path.to_string_lossy(),
+                    "result": [code, format_err_parsable(*code)]
+                })
+            })
+            .collect();
+        json!({
+            "name": name,
+            "result": [code, format_err_parsable(code)],
+            "dry_run": self.dry_run,
+            "duration": self.duration.as_secs_f64(),
+            "files": {
+                "considered": self.considered,
+                "copied": self.copied,
+                "skipped": {
+                    "newer": self.skipped_newer,
+                    "identical": self.skipped_identical,
+                    "exists": self.skipped_exists
+                },
+                "failed": failed,
+                "removed": self.removed
+            },
+            "bytes_copied": self.bytes_copied,
+            "failed_paths": failed_paths
+        })
+    }
+}
+
+// Log file where messages are written, regardless of console output, each
+// one on a line along with a timestamp and a severity: when a maximum size
+// is set, the file is rotated before it grows beyond that size, by renaming
+// it with a numeric suffix and keeping a fixed number of older files
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    level: Verbosity,
+}
+
+// there is a single log file at a time for the whole process, as messages
+// are written from several places, possibly by concurrent jobs
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+impl LogFile {
+    // open the log file for appending, creating its directory if needed
+    fn open(path: &Path, max_size: u64, level: Verbosity) -> std::io::Result<LogFile> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                create_dir_all(parent)?;
+            }
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path: PathBuf::from(path),
+            file,
+            size,
+            max_size,
+            level,
+        })
+    }
+
+    // name of an older log file, given its position among the rotated ones
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    // shift older log files by one position, dropping the oldest one, and
+    // start a new empty log file
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for index in (1..LOG_ROTATED_FILES).rev() {
+            let older = self.rotated_path(index);
+            if older.exists() {
+                fs::rename(&older, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        *self = LogFile::open(&self.path, self.max_size, self.level)?;
+        Ok(())
+    }
+
+    // write a line, rotating the file first if the line would not fit
+    fn write(&mut self, severity: &str, message: &str) {
+        use std::io::Write;
+        let now = OffsetDateTime::now_utc().to_offset(RUN_TIMESTAMP.offset());
+        let line = format!(
+            "{} {severity:<5} {message}\n",
+            format_timestamp(&now, "%Y-%m-%d %H:%M:%S"),
+        );
+        if self.max_size > 0 && self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            // a log file that cannot be rotated just keeps growing
+            let _ = self.rotate();
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+}
+
+// start writing messages up to the specified level to the specified log
+// file, unless it is already the current one, replacing the previous log
+// file if any
+fn open_log_file(path: &Path, max_size: u64, level: Verbosity) -> std::io::Result<()> {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    if let Some(current) = log_file.as_mut() {
+        if current.path == path {
+            current.max_size = max_size;
+            current.level = level;
+            return Ok(());
+        }
+    }
+    *log_file = Some(LogFile::open(path, max_size, level)?);
+    Ok(())
+}
+
+// change the level of the messages written to the current log file
+fn set_log_level(level: Verbosity) {
+    if let Some(log_file) = LOG_FILE.lock().unwrap().as_mut() {
+        log_file.level = level;
+    }
+}
+
+// stop writing messages to the log file
+fn close_log_file() {
+    *LOG_FILE.lock().unwrap() = None;
+}
+
+// tell whether or not messages of the specified level are being logged
+fn log_enabled(level: Verbosity) -> bool {
+    LOG_FILE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|log_file| level <= log_file.level)
+}
+
+// write a message of the specified level to the log file if any
+fn write_log(level: Verbosity, message: &str) {
+    if let Some(log_file) = LOG_FILE.lock().unwrap().as_mut() {
+        if level <= log_file.level {
+            log_file.write(level.severity(), message);
+        }
+    }
+}
+
+// Destination of the messages written while running a job: when jobs run
+// concurrently, messages are collected and then written as a single block
+// when the job ends, so that the output of different jobs does not mix;
+// messages are also written to the log file, always in readable form
+struct JobOutput {
+    buffered: bool,
+    verbosity: Verbosity,
+    parsable_output: bool,
+    lines: Mutex<Vec<(bool, String)>>,
+}
+
+impl JobOutput {
+    fn new(buffered: bool, verbosity: Verbosity, parsable_output: bool) -> JobOutput {
+        JobOutput {
+            buffered,
+            verbosity,
+            parsable_output,
+            lines: Mutex::new(Vec::new()),
+        }
+    }
+
+    // write a message of the specified level, given a function that formats
+    // it in either form, to the log file if any and, if the level is within
+    // the chosen verbosity, to stderr (errors) or stdout (other messages)
+    fn message(&self, level: Verbosity, message: impl Fn(bool) -> String) {
+        if log_enabled(level) {
+            write_log(level, &message(false));
+        }
+        if level <= self.verbosity {
+            if level == Verbosity::Errors {
+                self.eprintln(message(self.parsable_output));
+            } else {
+                self.println(message(self.parsable_output));
+            }
+        }
+    }
+
+    // write a line to stdout, or collect it
+    fn println(&self, line: String) {
+        if self.buffered {
+            self.lines.lock().unwrap().push((false, line));
+        } else {
+            clear_progress();
+            println!("{line}");
+        }
+    }
+
+    // write a line to stderr, or collect it
+    fn eprintln(&self, line: String) {
+        if self.buffered {
+            self.lines.lock().unwrap().push((true, line));
+        } else {
+            clear_progress();
+            eprintln!("{line}");
+        }
+    }
+
+    // write all collected lines, holding the locks on both streams so that
+    // no other job can write in between
+    fn flush(&self) {
+        use std::io::Write;
+        let lines = std::mem::take(&mut *self.lines.lock().unwrap());
+        if !lines.is_empty() {
+            clear_progress();
+        }
+        let mut stdout = std::io::stdout().lock();
+        let mut stderr = std::io::stderr().lock();
+        for (is_error, line) in lines {
+            if is_error {
+                let _ = writeln!(stderr, "{line}");
+            } else {
+                let _ = writeln!(stdout, "{line}");
+            }
+        }
+    }
+}
+
+// Progress of a running job, as files are copied
+struct JobProgress {
+    job: String,
+    files_total: usize,
+    files_done: usize,
+    bytes_total: u64,
+    bytes_done: u64,
+}
+
+// Progress of the running jobs, shown on a single line of the terminal that
+// is redrawn as files are copied: when jobs run concurrently, the line shows
+// the totals for all of them, and it is erased before any other message
+struct ProgressDisplay {
+    jobs: Vec<JobProgress>,
+    started: Instant,
+    last_drawn: Option<Instant>,
+    visible: bool,
+}
+
+// the progress display is only available when explicitly enabled
+static PROGRESS: Mutex<Option<ProgressDisplay>> = Mutex::new(None);
+
+impl ProgressDisplay {
+    // find the progress of a running job
+    fn job(&mut self, job: &str) -> Option<&mut JobProgress> {
+        self.jobs.iter_mut().find(|progress| progress.job == job)
+    }
+
+    // erase the progress line if shown
+    fn erase(&mut self) {
+        if self.visible {
+            eprint!("\r\x1b[2K");
+            self.visible = false;
+        }
+    }
+
+    // draw the progress line, unless it has just been drawn: throughput and
+    // remaining time are estimated from the bytes copied since the start
+    fn draw(&mut self, force: bool) {
+        let now = Instant::now();
+        if !force
+            && self
+                .last_drawn
+                .is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last_drawn = Some(now);
+        if self.jobs.is_empty() {
+            self.erase();
+            return;
+        }
+        let mut names = self
+            .jobs
+            .iter()
+            .map(|progress| progress.job.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        if names.chars().count() > 24 {
+            names = format!("{}...", names.chars().take(21).collect::<String>());
+        }
+        let files_done: usize = self.jobs.iter().map(|p| p.files_done).sum();
+        let files_total: usize = self.jobs.iter().map(|p| p.files_total).sum();
+        let bytes_total: u64 = self.jobs.iter().map(|p| p.bytes_total).sum();
+        let bytes_done: u64 = self
+            .jobs
+            .iter()
+            .map(|p| p.bytes_done)
+            .sum::<u64>()
+            .min(bytes_total);
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let throughput = if elapsed > 0.0 {
+            bytes_done as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if throughput > 0.0 {
+            format_duration(Duration::from_secs_f64(
+                (bytes_total - bytes_done) as f64 / throughput,
+            ))
+        } else {
+            String::from("--:--")
+        };
+        eprint!(
+            "\r\x1b[2K{names}: {files_done}/{files_total} files, {}/{} ({}/s), ETA {eta}",
+            format_bytes(bytes_done),
+            format_bytes(bytes_total),
+            format_bytes(throughput as u64),
+        );
+        self.visible = true;
+    }
+}
+
+// enable the progress display for the rest of the run
+fn enable_progress() {
+    *PROGRESS.lock().unwrap() = Some(ProgressDisplay {
+        jobs: Vec::new(),
+        started: Instant::now(),
+        last_drawn: None,
+        visible: false,
+    });
+}
+
+// tell whether or not progress is being displayed
+fn progress_enabled() -> bool {
+    PROGRESS.lock().unwrap().is_some()
+}
+
+// start showing the progress of a job, given the files and bytes to copy
+fn progress_begin_job(job: &str, files_total: usize, bytes_total: u64) {
+    if let Some(display) = PROGRESS.lock().unwrap().as_mut() {
+        if display.jobs.is_empty() {
+            display.started = Instant::now();
+        }
+        display.jobs.push(JobProgress {
+            job: String::from(job),
+            files_total,
+            files_done: 0,
+            bytes_total,
+            bytes_done: 0,
+        });
+        display.draw(true);
+    }
+}
+
+// account for bytes written while copying a file in a job
+fn progress_add_bytes(job: &str, bytes: u64) {
+    if let Some(display) = PROGRESS.lock().unwrap().as_mut() {
+        if let Some(progress) = display.job(job) {
+            progress.bytes_done += bytes;
+        }
+        display.draw(false);
+    }
+}
+
+// account for a file that has been processed, given its size and the bytes
+// written so far: the whole file counts as copied if the operation succeeded
+// (also when in dry run mode), otherwise it no longer counts at all
+fn progress_file_done(job: &str, size: u64, written: u64, copied: bool) {
+    if let Some(display) = PROGRESS.lock().unwrap().as_mut() {
+        if let Some(progress) = display.job(job) {
+            progress.files_done += 1;
+            if copied {
+                progress.bytes_done += size.saturating_sub(written);
+            } else {
+                progress.bytes_done = progress.bytes_done.saturating_sub(written);
+                progress.bytes_total = progress.bytes_total.saturating_sub(size);
+            }
+        }
+        display.draw(false);
+    }
+}
+
+// stop showing the progress of a job
+fn progress_end_job(job: &str) {
+    if let Some(display) = PROGRESS.lock().unwrap().as_mut() {
+        display.jobs.retain(|progress| progress.job != job);
+        display.draw(true);
+    }
+}
+
+// erase the progress line before writing other messages: it is drawn again
+// at the next update
+fn clear_progress() {
+    if let Some(display) = PROGRESS.lock().unwrap().as_mut() {
+        display.erase();
+    }
+}
+
+// helper to write a number of bytes in a readable form
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+// helper to write a duration as hours (if any), minutes and seconds
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+// set when an interruption (Ctrl-C) or termination signal is received
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// install the handler of interruption and termination signals: the first
+// signal lets the file being copied be completed, and then causes remaining
+// files, deletions and jobs to be skipped; a further signal terminates the
+// program immediately
+fn handle_interruptions() {
+    // failing to install the handler only leaves the default behavior
+    let _ = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+}
+
+// tell whether or not an interruption signal has been received
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Perform a single copy job, by building a list of files to copy and by
+/// copying them if possible using `copyfile` seen above. To be noticed that:
+///
+///     1) the job name has to be provided for reporting/logging purposes
+///     2) source_dir and destination_dir need to be passed to this function
+///        *after* replacing shortcuts and variables with their values, as no
+///        substitution is performed here
+///
+/// A description of the parameters follows:
+///
+///     job: &CopyJobConfig, containing all the job parameters
+///     cache: Option<&HashCache>, persistent cache of file digests
+///     output: &JobOutput, where messages are written and logged
+///     stats: &Mutex<JobStats>, where statistics about files are collected
+///
+/// When an interruption signal is received, the files being copied are
+/// completed and the job ends without copying or removing other files.
+///
+/// NOTE: writes to stdout/stderr (through output)
+/// NOTE: machine readable prefix of this section is JOB
+///
+/// As internal functions it also includes simple formatters for writing
+/// suitable messages when needed.
+fn run_single_job(
+    job: &CopyJobConfig,
+    cache: Option<&HashCache>,
+    output: &JobOutput,
+    stats: &Mutex<JobStats>,
+) -> Outcome {
+    // local helpers:
+
+    // l1. format a message (both machine readable and verbose output)
+    fn _format_message_rsj(
+        parsable_output: bool,
         job: &str,
         operation: &str,
         code: u64,
         source: &Path,
         destination: &Path,
+        bytes: Option<u64>,
     ) -> String {
         if parsable_output {
+            let path_or_null = |path: &Path| {
+                if path.as_os_str().is_empty() {
+                    None
+                } else {
+                    Some(path.to_string_lossy().to_string())
+                }
+            };
             format_output_parsable(
                 CONTEXT_JOB,
                 job,
@@ -1324,6 +4371,11 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
                 operation,
                 source.to_str().unwrap_or("<unknown>"),
                 destination.to_str().unwrap_or("<unknown>"),
+                json!({
+                    "source": path_or_null(source),
+                    "destination": path_or_null(destination),
+                    "bytes": bytes
+                }),
             )
         } else {
             match operation {
@@ -1334,6 +4386,13 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
                             source.display(),
                             destination.display(),
                         )
+                    } else if copy_skipped(code) {
+                        format!(
+                            "skipped in job {job}: {} => {}, because '{}'",
+                            source.display(),
+                            destination.display(),
+                            format_err_verbose(code),
+                        )
                     } else {
                         format!(
                             "error in job {job}: '{}' while copying {} => {}",
@@ -1354,6 +4413,39 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
                         )
                     }
                 }
+                OPERATION_JOB_CLEANUP => {
+                    format!(
+                        "removed leftover temporary file in job {job}: {}",
+                        destination.display(),
+                    )
+                }
+                OPERATION_JOB_DRYRUN_COPY => {
+                    if code == 0 {
+                        format!(
+                            "would copy in job {job}: {} => {}",
+                            source.display(),
+                            destination.display(),
+                        )
+                    } else {
+                        format!(
+                            "would skip in job {job}: '{}' while copying {} => {}",
+                            format_err_verbose(code),
+                            source.display(),
+                            destination.display(),
+                        )
+                    }
+                }
+                OPERATION_JOB_DRYRUN_DEL => {
+                    if code == 0 {
+                        format!("would remove in job {job}: {}", destination.display(),)
+                    } else {
+                        format!(
+                            "would skip in job {job}: '{}' while removing {}",
+                            format_err_verbose(code),
+                            destination.display(),
+                        )
+                    }
+                }
                 op => {
                     format!("unexpected operation: {op}")
                 }
@@ -1367,8 +4459,9 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
         job: &str,
         operation: &str,
         code: u64,
-        num_copy: usize,
-        num_delete: usize,
+        (num_copy, num_delete): (usize, usize),
+        dry_run: bool,
+        duration: Option<Duration>,
     ) -> String {
         if parsable_output {
             format_output_parsable(
@@ -1378,6 +4471,10 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
                 operation,
                 &format!("{num_copy}"),
                 &format!("{num_delete}"),
+                json!({
+                    "files": {"copy": num_copy, "remove": num_delete},
+                    "duration_ms": duration.map(|d| d.as_millis() as u64)
+                }),
             )
         } else {
             match operation {
@@ -1393,12 +4490,24 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
                     }
                 }
                 OPERATION_JOB_END => {
-                    if code == 0 {
+                    if code == 0 && dry_run {
+                        format!(
+                            "\
+                            results for job {job} (dry run): {num_copy} file(s) \
+                            would be copied, {num_delete} removed on destination"
+                        )
+                    } else if code == 0 {
                         format!(
                             "\
                             results for job {job}: {num_copy} file(s) copied, \
                             {num_delete} removed on destination"
                         )
+                    } else if code == CJERR_INTERRUPTED {
+                        format!(
+                            "\
+                            job {job} interrupted: {num_copy} file(s) copied, \
+                            {num_delete} removed on destination"
+                        )
                     } else {
                         format!("error in job {job}: '{}'", format_err_verbose(code))
                     }
@@ -1410,84 +4519,98 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
         }
     }
 
+    let started = Instant::now();
+    stats.lock().unwrap().dry_run = job.dry_run;
+
     // source and destination must exist and be canonicalizeable
     let source_directory = PathBuf::from(&job.source_dir.canonicalize().unwrap_or_default());
     if !source_directory.exists() {
-        if verbose {
-            eprintln!(
-                "{}",
-                _format_jobinfo_rsj(
-                    parsable_output,
-                    &job.job_name,
-                    OPERATION_JOB_BEGIN,
-                    CJERR_DESTINATION_DIR_NOT_EXISTS,
-                    0,
-                    0,
-                )
-            );
-        }
+        output.message(Verbosity::Errors, |parsable_output| {
+            _format_jobinfo_rsj(
+                parsable_output,
+                &job.job_name,
+                OPERATION_JOB_BEGIN,
+                CJERR_DESTINATION_DIR_NOT_EXISTS,
+                (0, 0),
+                job.dry_run,
+                Some(started.elapsed()),
+            )
+        });
         return Outcome::Error(CJERR_SOURCE_DIR_NOT_EXISTS);
     }
     if !job.destination_dir.exists() && !job.create_directories {
-        if verbose {
-            eprintln!(
-                "{}",
-                _format_jobinfo_rsj(
-                    parsable_output,
-                    &job.job_name,
-                    OPERATION_JOB_BEGIN,
-                    CJERR_DESTINATION_DIR_NOT_EXISTS,
-                    0,
-                    0,
-                )
-            );
-        }
+        output.message(Verbosity::Errors, |parsable_output| {
+            _format_jobinfo_rsj(
+                parsable_output,
+                &job.job_name,
+                OPERATION_JOB_BEGIN,
+                CJERR_DESTINATION_DIR_NOT_EXISTS,
+                (0, 0),
+                job.dry_run,
+                None,
+            )
+        });
         return Outcome::Error(CJERR_DESTINATION_DIR_NOT_EXISTS);
     }
 
-    // build the list of files to be copied
-    match list_files_matching(
-        &job.source_dir,
-        &job.include_pattern,
-        &job.exclude_pattern,
-        &job.excludedir_pattern,
-        job.recursive,
-        job.follow_symlinks,
-        job.case_sensitive,
-    ) {
-        Some(files_to_copy) => {
-            let mut num_files_copied: usize = 0;
+    // in dry run mode the operations are reported with specific identifiers
+    let (operation_copy, operation_del) = if job.dry_run {
+        (OPERATION_JOB_DRYRUN_COPY, OPERATION_JOB_DRYRUN_DEL)
+    } else {
+        (OPERATION_JOB_COPY, OPERATION_JOB_DEL)
+    };
+
+    // build the list of files to be copied and, if requested, the list of
+    // files that could be removed from the destination directory
+    let list_files = |dir: &PathBuf| {
+        list_files_matching(
+            dir,
+            &job.include_pattern,
+            &job.exclude_pattern,
+            &job.excludedir_pattern,
+            job.recursive,
+            job.follow_symlinks,
+            job.case_sensitive,
+        )
+    };
+    let file_lists = list_files(&job.source_dir).and_then(|files_to_copy| {
+        if job.remove_others_matching {
+            Ok((files_to_copy, list_files(&job.destination_dir)?))
+        } else {
+            Ok((files_to_copy, Vec::new()))
+        }
+    });
+    match file_lists {
+        Ok((files_to_copy, mut files_to_delete)) => {
             let mut num_files_deleted: usize = 0;
-            let mut files_to_delete = if job.remove_others_matching {
-                list_files_matching(
-                    &job.destination_dir,
-                    &job.include_pattern,
-                    &job.exclude_pattern,
-                    &job.excludedir_pattern,
-                    job.recursive,
-                    job.follow_symlinks,
-                    job.case_sensitive,
+            output.message(Verbosity::Jobs, |parsable_output| {
+                _format_jobinfo_rsj(
+                    parsable_output,
+                    &job.job_name,
+                    OPERATION_JOB_BEGIN,
+                    ERR_OK,
+                    (files_to_copy.len(), files_to_delete.len()),
+                    job.dry_run,
+                    None,
                 )
-                .unwrap_or_default()
-            } else {
-                Vec::new()
-            };
-            if verbose {
-                println!(
-                    "{}",
-                    _format_jobinfo_rsj(
-                        parsable_output,
-                        &job.job_name,
-                        OPERATION_JOB_BEGIN,
-                        ERR_OK,
-                        files_to_copy.len(),
-                        files_to_delete.len(),
-                    )
-                );
+            });
+            stats.lock().unwrap().considered = files_to_copy.len();
+            if progress_enabled() {
+                let bytes_total = files_to_copy
+                    .iter()
+                    .map(|item| metadata(item).map_or(0, |m| m.len()))
+                    .sum();
+                progress_begin_job(&job.job_name, files_to_copy.len(), bytes_total);
             }
+            // destination files are determined in advance, so that the list
+            // of files to delete is complete before any copy starts; copies
+            // that target the same destination (which may happen when the
+            // structure is flattened) are grouped to be performed in order
+            // by the same worker, instead of concurrently
+            let destination = PathBuf::from(&job.destination_dir);
+            let mut copy_groups: Vec<Vec<(PathBuf, Option<PathBuf>)>> = Vec::new();
+            let mut group_index: HashMap<PathBuf, usize> = HashMap::new();
             for item in files_to_copy {
-                // here we also copy the file
-                let destination = PathBuf::from(&job.destination_dir);
                 let destfile_relative: PathBuf = if job.keep_structure {
                     PathBuf::from(&item)
                         .strip_prefix(&job.source_dir)
@@ -1513,154 +4636,334 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
                                 .unwrap(),
                         ); // cannot panic here
                     }
-                    match copy_file(
-                        &item,
-                        &destfile_absolute,
-                        job.overwrite,
-                        job.skip_newer,
-                        job.check_content,
-                        job.follow_symlinks,
-                        job.create_directories,
-                        job.trash_on_overwrite,
-                    ) {
-                        Outcome::Success => {
-                            num_files_copied += 1;
-                            if verbose {
-                                println!(
-                                    "{}",
+                    match group_index.get(&destfile_absolute) {
+                        Some(index) => {
+                            copy_groups[*index].push((item, Some(destfile_absolute)));
+                        }
+                        None => {
+                            group_index.insert(destfile_absolute.clone(), copy_groups.len());
+                            copy_groups.push(vec![(item, Some(destfile_absolute))]);
+                        }
+                    }
+                } else {
+                    copy_groups.push(vec![(item, None)]);
+                }
+            }
+
+            // temporary files left behind by interrupted atomic copies of
+            // the destination files of this job are removed before starting,
+            // unless in dry run mode
+            if job.atomic_copy && !job.dry_run {
+                for item in remove_temporary_files(group_index.keys()) {
+                    output.message(Verbosity::Files, |parsable_output| {
+                        _format_message_rsj(
+                            parsable_output,
+                            &job.job_name,
+                            OPERATION_JOB_CLEANUP,
+                            ERR_OK,
+                            &PathBuf::new(),
+                            &item,
+                            None,
+                        )
+                    });
+                }
+            }
+
+            // copies are performed by a pool of workers, each picking the
+            // next group of copies to perform until none is left or an error
+            // requires the job to halt; every message is written as a whole
+            // line, so that output lines of different workers never mix; on
+            // interruption the copies in progress are completed, and no other
+            // copy is started
+            let num_files_copied = AtomicUsize::new(0);
+            let next_group = AtomicUsize::new(0);
+            let halt_error: Mutex<Option<u64>> = Mutex::new(None);
+            let copy_worker = || {
+                while halt_error.lock().unwrap().is_none() {
+                    let index = next_group.fetch_add(1, Ordering::SeqCst);
+                    if index >= copy_groups.len() {
+                        break;
+                    }
+                    for (item, destfile) in &copy_groups[index] {
+                        if interrupted() {
+                            halt_error.lock().unwrap().get_or_insert(CJERR_INTERRUPTED);
+                            return;
+                        }
+                        match destfile {
+                            Some(destfile_absolute) => {
+                                // the bytes written are accounted for when
+                                // showing progress
+                                let show_progress = progress_enabled();
+                                let written = std::cell::Cell::new(0);
+                                let on_bytes = |bytes: u64| {
+                                    written.set(written.get() + bytes);
+                                    progress_add_bytes(&job.job_name, bytes);
+                                };
+                                let outcome = copy_file(
+                                    item,
+                                    destfile_absolute,
+                                    job,
+                                    cache,
+                                    show_progress.then_some(&on_bytes as &dyn Fn(u64)),
+                                );
+                                if show_progress {
+                                    progress_file_done(
+                                        &job.job_name,
+                                        metadata(item).map_or(0, |m| m.len()),
+                                        written.get(),
+                                        matches!(outcome, Outcome::Success),
+                                    );
+                                }
+                                match outcome {
+                                    Outcome::Success => {
+                                        num_files_copied.fetch_add(1, Ordering::SeqCst);
+                                        let bytes = if job.dry_run {
+                                            0
+                                        } else {
+                                            metadata(item).map_or(0, |m| m.len())
+                                        };
+                                        stats.lock().unwrap().record(item, ERR_OK, Some(bytes));
+                                        output.message(Verbosity::Files, |parsable_output| {
+                                            _format_message_rsj(
+                                                parsable_output,
+                                                &job.job_name,
+                                                operation_copy,
+                                                ERR_OK,
+                                                item,
+                                                destfile_absolute,
+                                                Some(bytes),
+                                            )
+                                        });
+                                    }
+                                    Outcome::Error(err) => {
+                                        stats.lock().unwrap().record(item, err, None);
+                                        output.message(
+                                            if copy_skipped(err) {
+                                                Verbosity::Debug
+                                            } else {
+                                                Verbosity::Errors
+                                            },
+                                            |parsable_output| {
+                                                _format_message_rsj(
+                                                    parsable_output,
+                                                    &job.job_name,
+                                                    operation_copy,
+                                                    err,
+                                                    item,
+                                                    destfile_absolute,
+                                                    None,
+                                                )
+                                            },
+                                        );
+                                        if job.halt_on_errors {
+                                            halt_error
+                                                .lock()
+                                                .unwrap()
+                                                .get_or_insert(CJERR_GENERIC_FAILURE);
+                                            return;
+                                        };
+                                    }
+                                };
+                            }
+                            None => {
+                                stats.lock().unwrap().record(
+                                    item,
+                                    CJERR_CANNOT_DETERMINE_DESTFILE,
+                                    None,
+                                );
+                                progress_file_done(
+                                    &job.job_name,
+                                    metadata(item).map_or(0, |m| m.len()),
+                                    0,
+                                    false,
+                                );
+                                output.message(Verbosity::Errors, |parsable_output| {
                                     _format_message_rsj(
                                         parsable_output,
                                         &job.job_name,
-                                        OPERATION_JOB_COPY,
-                                        ERR_OK,
-                                        &item,
-                                        &destfile_absolute,
+                                        operation_copy,
+                                        CJERR_CANNOT_DETERMINE_DESTFILE,
+                                        item,
+                                        &destination,
+                                        None,
                                     )
-                                );
+                                });
+                                if job.halt_on_errors {
+                                    halt_error
+                                        .lock()
+                                        .unwrap()
+                                        .get_or_insert(CJERR_CANNOT_DETERMINE_DESTFILE);
+                                    return;
+                                }
                             }
                         }
-                        Outcome::Error(err) => {
-                            if verbose {
-                                eprintln!(
-                                    "{}",
-                                    _format_message_rsj(
-                                        parsable_output,
-                                        &job.job_name,
-                                        OPERATION_JOB_COPY,
-                                        err,
-                                        &item,
-                                        &destfile_absolute,
-                                    )
-                                );
-                            }
-                            if job.halt_on_errors {
-                                return Outcome::Error(CJERR_GENERIC_FAILURE);
-                            };
-                        }
-                    };
-                } else {
-                    if verbose {
-                        eprintln!(
-                            "{}",
-                            _format_message_rsj(
-                                parsable_output,
-                                &job.job_name,
-                                OPERATION_JOB_COPY,
-                                CJERR_CANNOT_DETERMINE_DESTFILE,
-                                &item,
-                                &destination,
-                            )
-                        );
-                    }
-                    if job.halt_on_errors {
-                        return Outcome::Error(CJERR_CANNOT_DETERMINE_DESTFILE);
                     }
                 }
+            };
+            let num_workers = job.parallelism.clamp(1, copy_groups.len().max(1));
+            if num_workers > 1 {
+                std::thread::scope(|scope| {
+                    for _ in 0..num_workers {
+                        scope.spawn(copy_worker);
+                    }
+                });
+            } else {
+                copy_worker();
+            }
+            let halted = *halt_error.lock().unwrap();
+            if let Some(err) = halted.filter(|err| *err != CJERR_INTERRUPTED) {
+                return Outcome::Error(err);
             }
-            // if not remove_other_matching the vector is empty
+            let num_files_copied = num_files_copied.into_inner();
+
+            // if not remove_other_matching the vector is empty, and files
+            // are not removed at all after an interruption
+            let mut code = halted.unwrap_or(ERR_OK);
             for item in files_to_delete {
-                match remove_file(&item, job.follow_symlinks, job.trash_on_delete) {
+                if code != ERR_OK || interrupted() {
+                    code = CJERR_INTERRUPTED;
+                    break;
+                }
+                let outcome =
+                    remove_file(&item, job.follow_symlinks, job.trash_on_delete, job.dry_run);
+                stats.lock().unwrap().record(
+                    &item,
+                    match outcome {
+                        Outcome::Success => ERR_OK,
+                        Outcome::Error(err) => err,
+                    },
+                    None,
+                );
+                match outcome {
                     Outcome::Success => {
-                        if verbose {
-                            println!(
-                                "{}",
-                                _format_message_rsj(
-                                    parsable_output,
-                                    &job.job_name,
-                                    OPERATION_JOB_DEL,
-                                    ERR_OK,
-                                    &PathBuf::new(),
-                                    &item,
-                                )
-                            );
-                        }
+                        output.message(Verbosity::Files, |parsable_output| {
+                            _format_message_rsj(
+                                parsable_output,
+                                &job.job_name,
+                                operation_del,
+                                ERR_OK,
+                                &PathBuf::new(),
+                                &item,
+                                None,
+                            )
+                        });
                         num_files_deleted += 1;
                     }
                     Outcome::Error(err) => {
-                        if verbose {
-                            eprintln!(
-                                "{}",
-                                _format_message_rsj(
-                                    parsable_output,
-                                    &job.job_name,
-                                    OPERATION_JOB_DEL,
-                                    err,
-                                    &PathBuf::new(),
-                                    &item,
-                                )
-                            );
-                        }
+                        output.message(Verbosity::Errors, |parsable_output| {
+                            _format_message_rsj(
+                                parsable_output,
+                                &job.job_name,
+                                operation_del,
+                                err,
+                                &PathBuf::new(),
+                                &item,
+                                None,
+                            )
+                        });
                         if job.halt_on_errors {
                             return Outcome::Error(CJERR_GENERIC_FAILURE);
                         };
                     }
                 }
             }
-            if verbose {
-                println!(
-                    "{}",
-                    _format_jobinfo_rsj(
-                        parsable_output,
-                        &job.job_name,
-                        OPERATION_JOB_END,
-                        ERR_OK,
-                        num_files_copied,
-                        num_files_deleted,
-                    )
-                );
+            let level = if code == ERR_OK {
+                Verbosity::Jobs
+            } else {
+                Verbosity::Errors
+            };
+            output.message(level, |parsable_output| {
+                _format_jobinfo_rsj(
+                    parsable_output,
+                    &job.job_name,
+                    OPERATION_JOB_END,
+                    code,
+                    (num_files_copied, num_files_deleted),
+                    job.dry_run,
+                    Some(started.elapsed()),
+                )
+            });
+            if code != ERR_OK {
+                return Outcome::Error(code);
             }
         }
-        None => {
-            if verbose {
-                eprintln!(
-                    "{}",
-                    _format_jobinfo_rsj(
-                        parsable_output,
-                        &job.job_name,
-                        OPERATION_JOB_END,
-                        CJERR_NO_SOURCE_FILES,
-                        0,
-                        0,
-                    )
+        Err(e) => {
+            output.message(Verbosity::Errors, |parsable_output| {
+                let msg = _format_jobinfo_rsj(
+                    parsable_output,
+                    &job.job_name,
+                    OPERATION_JOB_BEGIN,
+                    ERR_INVALID_PATTERN,
+                    (0, 0),
+                    job.dry_run,
+                    None,
                 );
-            }
-            return Outcome::Error(CJERR_NO_SOURCE_FILES);
+                if parsable_output {
+                    msg
+                } else {
+                    format!("{msg} ({})", regexp_error_message(&e))
+                }
+            });
+            return Outcome::Error(ERR_INVALID_PATTERN);
         }
     }
 
     Outcome::Success
 }
 
+// Outcome of the jobs performed for a configuration file: the job names
+// along with their result codes and statistics, in the order in which jobs
+// ended, and whether or not the remaining jobs were not started after a
+// failure
+struct JobsOutcome {
+    results: Vec<(String, u64, JobStats)>,
+    halted: bool,
+}
+
+impl JobsOutcome {
+    // exit code corresponding to the aggregated outcome of the jobs: failed
+    // jobs are more severe than failed operations on files in jobs that were
+    // anyway completed, while skipped files are not considered failures
+    fn exit_code(&self) -> i32 {
+        if self.halted || self.results.iter().any(|(_, code, _)| *code != ERR_OK) {
+            EXIT_JOBS_FAILED
+        } else if self
+            .results
+            .iter()
+            .any(|(_, _, stats)| !stats.failed.is_empty())
+        {
+            EXIT_FILES_FAILED
+        } else {
+            EXIT_SUCCESS
+        }
+    }
+}
+
+// exit code corresponding to the error that prevented the jobs defined in a
+// configuration file from being run
+fn error_exit_code(code: u64) -> i32 {
+    match code {
+        ERR_INTERRUPTED => EXIT_INTERRUPTED,
+        ERR_LOCK_CONTENTION => EXIT_LOCK_CONTENTION,
+        _ => EXIT_INVALID_CONFIG,
+    }
+}
+
 /// Perform all jobs, according to the passed global config object and list
 /// of job configuration objects, that is the result of extract_config as
 /// defined above. A brief description of the arguments follows:
 ///
 ///     global_config: &CopyJobGlobalConfig, global configuration
-///     job_configs: &Vec<CopyJobConfig>, full list of job configurations
+///     job_configs: &[CopyJobConfig], full list of job configurations
+///     cache: Option<&HashCache>, persistent cache of file digests
 ///
 /// This function selects the jobs to actually perform according to the
 /// list of names provided in global_config.active_jobs, so the full list
-/// of jobs found in the configuration file can be provided.
+/// of jobs found in the configuration file can be provided. Jobs are run
+/// after the jobs they depend on, and skipped if any of them failed. The
+/// result code of each job that was either run or skipped is returned, and
+/// an error is only returned if the jobs cannot be ordered. No job is started
+/// after an interruption signal has been received.
 ///
 /// NOTE: writes to stdout/stderr
 /// NOTE: machine readable prefix of this section is TASK
@@ -1669,71 +4972,337 @@ fn run_single_job(job: &CopyJobConfig, verbose: bool, parsable_output: bool) ->
 /// suitable messages when needed.
 fn run_jobs(
     global_config: &CopyJobGlobalConfig,
-    job_configs: &Vec<CopyJobConfig>,
-) -> std::io::Result<()> {
+    job_configs: &[CopyJobConfig],
+    cache: Option<&HashCache>,
+) -> std::io::Result<JobsOutcome> {
     // local helpers:
 
     // l1. format a message (both machine readable and verbose output)
-    fn _format_message_rj(parsable_output: bool, job: &str, code: u64) -> String {
+    fn _format_message_rj(
+        parsable_output: bool,
+        job: &str,
+        code: u64,
+        duration: Duration,
+    ) -> String {
         if parsable_output {
-            format_output_parsable(CONTEXT_TASK, job, code, OPERATION_JOB_END, "", "")
+            format_output_parsable(
+                CONTEXT_TASK,
+                job,
+                code,
+                OPERATION_JOB_END,
+                "",
+                "",
+                json!({"duration_ms": duration.as_millis() as u64}),
+            )
         } else if code == 0 {
             format!("job {job} completed successfully")
+        } else if code == CJERR_INTERRUPTED {
+            format!("job {job} interrupted")
         } else {
             format!("job {job} failed with error '{}'", format_err_verbose(code))
         }
     }
 
-    for job in job_configs {
-        if global_config.active_jobs.contains(&job.job_name) {
-            match run_single_job(job, global_config.verbose, global_config.parsable_output) {
-                Outcome::Success => {
-                    if global_config.verbose {
-                        println!(
-                            "{}",
-                            _format_message_rj(global_config.parsable_output, &job.job_name, 0)
-                        );
-                    }
+    // l2. tell whether two destination directories overlap, that is, either
+    // of them contains the other
+    fn _overlapping_rj(dir1: &Path, dir2: &Path) -> bool {
+        let dir1 = dir1.canonicalize().unwrap_or(PathBuf::from(dir1));
+        let dir2 = dir2.canonicalize().unwrap_or(PathBuf::from(dir2));
+        dir1.starts_with(&dir2) || dir2.starts_with(&dir1)
+    }
+
+    // only active jobs are run, in the order in which they are defined but
+    // so that every job follows the jobs it depends on: a job that depends
+    // on jobs that are not active is skipped, as its prerequisites are not
+    // met, and cycles are normally detected when reading the configuration
+    let active: Vec<&CopyJobConfig> = job_configs
+        .iter()
+        .filter(|job| global_config.active_jobs.contains(&job.job_name))
+        .collect();
+    let names: Vec<String> = active.iter().map(|job| job.job_name.clone()).collect();
+    let dependencies: Vec<Vec<String>> = active.iter().map(|job| job.depends_on.clone()).collect();
+    let jobs: Vec<&CopyJobConfig> = match dependency_order(&names, &dependencies) {
+        Ok(order) => order.iter().map(|index| active[*index]).collect(),
+        Err(cycle) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{}:{}",
+                    format_err_parsable(ERR_INVALID_CONFIG_FILE),
+                    cycle.join("->")
+                ),
+            ));
+        }
+    };
+    let requires: Vec<Vec<usize>> = jobs
+        .iter()
+        .map(|job| {
+            job.depends_on
+                .iter()
+                .filter_map(|dep| jobs.iter().position(|other| other.job_name == *dep))
+                .collect()
+        })
+        .collect();
+    let unmet: Vec<bool> = jobs
+        .iter()
+        .zip(requires.iter())
+        .map(|(job, required)| required.len() < job.depends_on.len())
+        .collect();
+    let overlaps: Vec<Vec<bool>> = jobs
+        .iter()
+        .map(|job1| {
+            jobs.iter()
+                .map(|job2| _overlapping_rj(&job1.destination_dir, &job2.destination_dir))
+                .collect()
+        })
+        .collect();
+
+    // jobs are run by a pool of workers, each picking the first pending job
+    // whose dependencies are completed and that does not overlap with a
+    // running job nor with a pending job that comes before it, so that jobs
+    // writing to the same directories are still run one after the other and
+    // in their original order; a job whose dependencies failed or are not
+    // run is skipped, and is considered failed in turn; when jobs run concurrently their
+    // text messages are written in blocks, while the machine readable
+    // messages already carry the job name on each line
+    struct JobQueue {
+        pending: Vec<usize>,
+        running: Vec<usize>,
+        failed: Vec<bool>,
+        halted: bool,
+        results: Vec<(String, u64, JobStats)>,
+    }
+    let num_workers = global_config.max_parallel_jobs.clamp(1, jobs.len().max(1));
+    let buffered = num_workers > 1 && !global_config.parsable_output;
+    let queue = Mutex::new(JobQueue {
+        pending: (0..jobs.len()).collect(),
+        running: Vec::new(),
+        failed: vec![false; jobs.len()],
+        halted: false,
+        results: Vec::new(),
+    });
+    let queue_changed = Condvar::new();
+    let job_worker = || loop {
+        let (index, skip) = {
+            let mut queue = queue.lock().unwrap();
+            loop {
+                if queue.halted || queue.pending.is_empty() || interrupted() {
+                    return;
                 }
-                Outcome::Error(code) => {
-                    if global_config.verbose {
-                        println!(
-                            "{}",
-                            _format_message_rj(global_config.parsable_output, &job.job_name, code)
-                        );
-                    }
-                    if global_config.halt_on_errors {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::Interrupted,
-                            format_err_parsable(ERR_GENERIC),
-                        ));
+                let ready = queue.pending.iter().enumerate().position(|(pos, &index)| {
+                    requires[index]
+                        .iter()
+                        .all(|dep| !queue.pending.contains(dep) && !queue.running.contains(dep))
+                        && !queue.running.iter().any(|&other| overlaps[other][index])
+                        && !queue.pending[..pos]
+                            .iter()
+                            .any(|&other| overlaps[other][index])
+                });
+                match ready {
+                    Some(pos) => {
+                        let index = queue.pending.remove(pos);
+                        queue.running.push(index);
+                        let skip =
+                            unmet[index] || requires[index].iter().any(|dep| queue.failed[*dep]);
+                        break (index, skip);
                     }
+                    None => queue = queue_changed.wait(queue).unwrap(),
                 }
             }
+        };
+        let job = jobs[index];
+        let output = JobOutput::new(
+            buffered,
+            global_config.verbosity,
+            global_config.parsable_output,
+        );
+        let stats = Mutex::new(JobStats::default());
+        let started = Instant::now();
+        let code = if skip {
+            CJERR_DEPENDENCY_FAILED
+        } else {
+            match run_single_job(job, cache, &output, &stats) {
+                Outcome::Success => ERR_OK,
+                Outcome::Error(code) => code,
+            }
+        };
+        progress_end_job(&job.job_name);
+        let mut stats = stats.into_inner().unwrap();
+        stats.duration = started.elapsed();
+        let message = |parsable_output| {
+            _format_message_rj(parsable_output, &job.job_name, code, stats.duration)
+        };
+        if code == ERR_OK {
+            output.message(Verbosity::Jobs, message);
+        } else {
+            output.message(Verbosity::Errors, message);
+        }
+        output.flush();
+        let mut queue = queue.lock().unwrap();
+        queue.running.retain(|&other| other != index);
+        queue.results.push((job.job_name.clone(), code, stats));
+        if code != ERR_OK {
+            queue.failed[index] = true;
+            if global_config.halt_on_errors {
+                queue.halted = true;
+            }
         }
+        queue_changed.notify_all();
+    };
+    if num_workers > 1 {
+        std::thread::scope(|scope| {
+            for _ in 0..num_workers {
+                scope.spawn(job_worker);
+            }
+        });
+    } else {
+        job_worker();
     }
 
-    Ok(())
+    // when halting on errors or after an interruption, jobs that are still
+    // pending are not started
+    let queue = queue.into_inner().unwrap();
+    Ok(JobsOutcome {
+        results: queue.results,
+        halted: queue.halted,
+    })
 }
 
 // argument parsing and command execution: doc comments are used by clap
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 
 /// Perform complex copy jobs according to criteria provided in a TOML file
 #[derive(Parser)]
-#[command(name = "copyjob", version, about)]
+#[command(
+    name = "copyjob",
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Suppress all output
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Only report errors
+    #[arg(short, long, global = true)]
     quiet: bool,
 
+    /// Report actions on single files (-v, the default), and also debugging information (-vv)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Level of detail of messages, overriding the configuration files
+    #[arg(
+        long = "log-level",
+        value_name = "LEVEL",
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    log_level: Option<Verbosity>,
+
     /// Generate machine readable output (JSON)
-    #[arg(short = 'p', long = "parsable-output")]
+    #[arg(short = 'p', long = "parsable-output", global = true)]
     parsable_output: bool,
 
-    /// path to configuration file
-    #[arg()]
-    config: String,
+    /// Format of machine readable output (implies --parsable-output)
+    #[arg(long = "output-format", value_name = "FORMAT", global = true)]
+    output_format: Option<OutputFormat>,
+
+    /// Report planned operations without modifying any file
+    #[arg(short = 'n', long = "dry-run")]
+    dry_run: bool,
+
+    /// Run the specified job instead of active jobs (repeatable)
+    #[arg(long = "job", value_name = "NAME")]
+    job: Vec<String>,
+
+    /// Run all jobs defined in the configuration file
+    #[arg(long = "all-jobs", conflicts_with = "job")]
+    all_jobs: bool,
+
+    /// Do not run the specified job even if active (repeatable)
+    #[arg(long = "exclude-job", value_name = "NAME")]
+    exclude_job: Vec<String>,
+
+    /// Number of files to copy concurrently in each job (0: one per CPU)
+    #[arg(short = 'j', long = "jobs", value_name = "N")]
+    jobs: Option<u32>,
+
+    /// Ignore cached digests and compute them again
+    #[arg(long = "rebuild-cache")]
+    rebuild_cache: bool,
+
+    /// Set a local variable, overriding its definitions (repeatable)
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_variable_assignment, global = true)]
+    set: Vec<(String, String)>,
+
+    /// Run the configuration files found in a directory and its subdirectories
+    #[arg(long = "scan", value_name = "DIR")]
+    scan: Option<String>,
+
+    /// Show the progress of jobs (only on terminals, without parsable output)
+    #[arg(long = "progress")]
+    progress: bool,
+
+    /// Write a summary of the run to the specified file (JSON)
+    #[arg(long = "report", value_name = "FILE")]
+    report: Option<String>,
+
+    /// Also write messages to the specified log file
+    #[arg(long = "log-file", value_name = "PATH")]
+    log_file: Option<String>,
+
+    /// Rotate the log file when it would grow beyond the specified size
+    #[arg(long = "log-max-size", value_name = "BYTES")]
+    log_max_size: Option<u64>,
+
+    /// Names of configuration files to run when scanning a directory
+    #[arg(
+        long = "pattern",
+        value_name = "PATTERN",
+        default_value = "copyjob*.toml",
+        requires = "scan"
+    )]
+    pattern: String,
+
+    /// path to configuration file(s)
+    #[arg(required_unless_present = "scan")]
+    config: Vec<String>,
+}
+
+// formats of machine readable output: the original format, where arguments
+// depend on the operation, remains the default for compatibility
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    #[value(name = "jsonl")]
+    Jsonl,
+    #[value(name = "jsonl-v2")]
+    JsonlV2,
+}
+
+// the output format is chosen once for the whole run
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+// parser for variable assignments given on the command line
+fn parse_variable_assignment(assignment: &str) -> Result<(String, String), String> {
+    match assignment.split_once('=') {
+        Some((name, value)) if RE_VARNAME.is_match(name) => {
+            Ok((String::from(name), String::from(value)))
+        }
+        Some((name, _)) => Err(format!("invalid variable name '{name}'")),
+        None => Err(String::from("expected NAME=VALUE")),
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check a configuration file and report all problems found
+    Validate {
+        /// path to configuration file
+        #[arg()]
+        config: String,
+    },
 }
 
 // entry point: mandatory arguments are handled by the parser
@@ -1743,14 +5312,19 @@ fn main() -> std::io::Result<()> {
         parsable_output: bool,
         operation: &str,
         name: &str,
-        e: Option<std::io::Error>,
+        e: Option<&std::io::Error>,
+        code: u64,
         msg_parsable: &str,
         msg_verbose: &str,
     ) -> String {
         match e {
             Some(err) => {
                 if parsable_output {
-                    let code = u64::try_from(err.raw_os_error().unwrap_or(9999)).unwrap_or(9999);
+                    // OS errors are reported with their own code
+                    let code = match err.raw_os_error() {
+                        Some(os_code) => u64::try_from(os_code).unwrap_or(ERR_GENERIC),
+                        None => code,
+                    };
                     format_output_parsable(
                         CONTEXT_MAIN,
                         name,
@@ -1758,6 +5332,7 @@ fn main() -> std::io::Result<()> {
                         operation,
                         msg_parsable,
                         &err.to_string(),
+                        json!({"message": err.to_string()}),
                     )
                 } else {
                     format!("error: {msg_verbose} / {err}")
@@ -1765,7 +5340,15 @@ fn main() -> std::io::Result<()> {
             }
             _ => {
                 if parsable_output {
-                    format_output_parsable(CONTEXT_MAIN, name, ERR_OK, operation, msg_parsable, "")
+                    format_output_parsable(
+                        CONTEXT_MAIN,
+                        name,
+                        code,
+                        operation,
+                        msg_parsable,
+                        "",
+                        json!({}),
+                    )
                 } else {
                     format!("info: {msg_verbose}")
                 }
@@ -1773,92 +5356,1294 @@ fn main() -> std::io::Result<()> {
         }
     }
 
-    let args = Args::parse();
+    // levels of detail of console output and of the log file, given the one
+    // specified in the configuration file if any: the command line options
+    // override it, and quiet mode does not affect the log file; the default
+    // reports every file, as the output of earlier versions did
+    fn _verbosity_main(args: &Args, configured: Option<Verbosity>) -> (Verbosity, Verbosity) {
+        let level = match (args.log_level, args.verbose) {
+            (Some(level), _) => level,
+            (None, 0) => configured.unwrap_or(Verbosity::Files),
+            (None, 1) => Verbosity::Files,
+            _ => Verbosity::Debug,
+        };
+        (if args.quiet { Verbosity::Errors } else { level }, level)
+    }
 
-    // configuration file name is canonicalized in order to get a correct
-    // UNICODE path that includes the prefix, so that substitutions in
-    // destination file names can be performed without error; an empty
-    // PathBuf is produced if the file path does not exist, and this will
-    // cause an error while reading the configuration
-    let config = extract_config(
-        &PathBuf::from(args.config)
-            .canonicalize()
-            .unwrap_or(PathBuf::new()),
-        !args.quiet,
-        args.parsable_output,
-    );
+    // write a message of the specified level to the console, if within the
+    // chosen verbosity, and to the log file if any, given a function that
+    // formats it in either form
+    fn _output_main(
+        args: &Args,
+        verbosity: Verbosity,
+        level: Verbosity,
+        message: impl Fn(bool) -> String,
+    ) {
+        clear_progress();
+        if log_enabled(level) {
+            // the severity is already part of each line in the log file
+            let line = message(false);
+            let line = line
+                .strip_prefix("error: ")
+                .or(line.strip_prefix("info: "))
+                .unwrap_or(&line);
+            write_log(level, line);
+        }
+        if level <= verbosity {
+            if level == Verbosity::Errors {
+                eprintln!("{}", message(args.parsable_output));
+            } else {
+                println!("{}", message(args.parsable_output));
+            }
+        }
+    }
+
+    // formatter to write a configuration problem found by validation
+    fn _format_problem_main(parsable_output: bool, name: &str, problem: &ConfigProblem) -> String {
+        if parsable_output && OUTPUT_FORMAT.get() == Some(&OutputFormat::JsonlV2) {
+            format_output_event(
+                CONTEXT_MAIN,
+                name,
+                ERR_INVALID_CONFIG_FILE,
+                OPERATION_VALIDATE,
+                json!({
+                    "message": problem.message,
+                    "file": problem.file.to_string_lossy(),
+                    "line": problem.line,
+                    "column": problem.column,
+                    "key": if problem.key.is_empty() { None } else { Some(&problem.key) }
+                }),
+            )
+        } else if parsable_output {
+            json!({
+                "context": CONTEXT_MAIN,
+                "message_type": "ERROR",
+                "result": [
+                    ERR_INVALID_CONFIG_FILE,
+                    format_err_parsable(ERR_INVALID_CONFIG_FILE)
+                ],
+                "operation": [OPERATION_VALIDATE, name],
+                "args": [
+                    if problem.key.is_empty() { "<N/A>" } else { &problem.key },
+                    problem.message
+                ],
+                "file": problem.file.to_string_lossy(),
+                "position": [problem.line, problem.column]
+            })
+            .to_string()
+        } else if problem.key.is_empty() {
+            format!(
+                "error: {}:{}:{}: {}",
+                problem.file.display(),
+                problem.line,
+                problem.column,
+                problem.message
+            )
+        } else {
+            format!(
+                "error: {}:{}:{}: {}: {}",
+                problem.file.display(),
+                problem.line,
+                problem.column,
+                problem.key,
+                problem.message
+            )
+        }
+    }
+
+    // the local time zone can only be determined safely while the process
+    // is single threaded, therefore the time of the run is fixed right away
+    lazy_static::initialize(&RUN_TIMESTAMP);
+    let run_started = Instant::now();
+
+    // formatter to write the combined summary of several configuration files,
+    // where both arguments consist of the total and failed items
+    fn _format_summary_main(
+        parsable_output: bool,
+        configs: (usize, usize),
+        jobs: (usize, usize),
+    ) -> String {
+        let code = if configs.1 > 0 { ERR_GENERIC } else { ERR_OK };
+        if parsable_output && OUTPUT_FORMAT.get() == Some(&OutputFormat::JsonlV2) {
+            format_output_event(
+                CONTEXT_MAIN,
+                "",
+                code,
+                OPERATION_SUMMARY,
+                json!({
+                    "configs": {"total": configs.0, "failed": configs.1},
+                    "jobs": {"total": jobs.0, "failed": jobs.1}
+                }),
+            )
+        } else if parsable_output {
+            json!({
+                "context": CONTEXT_MAIN,
+                "message_type": if code == ERR_OK { "INFO" } else { "ERROR" },
+                "result": [code, format_err_parsable(code)],
+                "operation": [OPERATION_SUMMARY, "<N/A>"],
+                "args": [
+                    format!("{}/{}", configs.0 - configs.1, configs.0),
+                    format!("{}/{}", jobs.0 - jobs.1, jobs.0)
+                ],
+                "configs": {"total": configs.0, "failed": configs.1},
+                "jobs": {"total": jobs.0, "failed": jobs.1}
+            })
+            .to_string()
+        } else {
+            format!(
+                "info: {} configuration file(s) processed, {} failed: {} job(s) run, {} failed",
+                configs.0, configs.1, jobs.0, jobs.1
+            )
+        }
+    }
+
+    // run the jobs defined in a configuration file, applying the overrides
+    // given on the command line: the outcome of jobs is returned, or an error
+    // along with its code if the configuration file cannot be used
+    fn _run_config_main(
+        args: &Args,
+        config_file: &Path,
+    ) -> Result<JobsOutcome, (std::io::Error, u64)> {
+        let (mut global, mut jobs) = extract_config(
+            config_file,
+            _verbosity_main(args, None).0,
+            args.parsable_output,
+            &args.job,
+            args.all_jobs,
+            &args.exclude_job,
+            &args.set,
+        )
+        .map_err(|e| {
+            // invalid patterns and undefined variables are reported with
+            // their own error codes
+            let code = [ERR_INVALID_PATTERN, ERR_UNDEFINED_VARIABLE]
+                .into_iter()
+                .find(|code| e.to_string().starts_with(&format_err_parsable(*code)))
+                .unwrap_or(ERR_INVALID_CONFIG_FILE);
+            (e, code)
+        })?;
+
+        // the log file given on the command line is used for all configuration
+        // files, otherwise the one specified in each configuration file is;
+        // the level of detail can be specified in the configuration file
+        let (verbosity, log_verbosity) = _verbosity_main(args, global.log_level);
+        global.verbosity = verbosity;
+        if args.log_file.is_none() {
+            if let Some(log_file) = &global.log_file {
+                open_log_file(
+                    log_file,
+                    args.log_max_size.unwrap_or(global.log_max_size),
+                    log_verbosity,
+                )
+                .map_err(|e| (e, ERR_CANNOT_OPEN_LOG))?;
+            }
+        } else {
+            set_log_level(log_verbosity);
+        }
+
+        // dry run mode requested on the command line overrides all jobs
+        if args.dry_run {
+            global.dry_run = true;
+            for job in jobs.iter_mut() {
+                job.dry_run = true;
+            }
+        }
+
+        // and so does the number of files to copy concurrently
+        if let Some(value) = args.jobs {
+            // cannot fail, as the value is never negative
+            let parallelism = parallelism_from_value(i64::from(value)).unwrap_or(1);
+            global.parallelism = parallelism;
+            for job in jobs.iter_mut() {
+                job.parallelism = parallelism;
+            }
+        }
 
-    match config {
-        Ok((global, jobs)) => {
-            if !args.quiet {
-                println!(
-                    "{}",
+        _output_main(args, global.verbosity, Verbosity::Jobs, |parsable_output| {
+            _format_message_main(
+                parsable_output,
+                OPERATION_CONFIG,
+                global.config_file.as_os_str().to_str().unwrap_or(""),
+                None,
+                ERR_OK,
+                "",
+                &format!(
+                    "using configuration file {}",
+                    global.config_file.as_os_str().to_str().unwrap_or(""),
+                ),
+            )
+        });
+
+        // runs of the same configuration file can be prevented from overlapping
+        // using a lock file, that stays locked until all jobs have run and is
+        // released by the operating system even if the program is terminated
+        let _lock = match &global.lock_file {
+            Some(lock_file) => Some(_lock_main(lock_file)?),
+            None => None,
+        };
+
+        // the hash cache is loaded only if configured, and saved after all
+        // jobs have run regardless of their outcome: a failure in saving it
+        // is reported but does not affect the result
+        let cache = global
+            .hash_cache
+            .as_ref()
+            .map(|path| HashCache::load(path, args.rebuild_cache));
+        let result = run_jobs(&global, &jobs, cache.as_ref());
+        // nothing is written in dry run mode, not even the cache
+        let dry_run = global.dry_run
+            || jobs
+                .iter()
+                .any(|job| job.dry_run && global.active_jobs.contains(&job.job_name));
+        if let Some(cache) = cache.as_ref().filter(|_| !dry_run) {
+            if let Err(e) = cache.save() {
+                _output_main(
+                    args,
+                    global.verbosity,
+                    Verbosity::Errors,
+                    |parsable_output| {
+                        _format_message_main(
+                            parsable_output,
+                            OPERATION_CACHE,
+                            cache.path.as_os_str().to_str().unwrap_or(""),
+                            Some(&e),
+                            ERR_CANNOT_SAVE_CACHE,
+                            &format_err_parsable(ERR_CANNOT_SAVE_CACHE),
+                            &format_err_verbose(ERR_CANNOT_SAVE_CACHE),
+                        )
+                    },
+                );
+            }
+        }
+        // jobs can only fail to be ordered if their dependencies are circular
+        result.map_err(|e| (e, ERR_INVALID_CONFIG_FILE))
+    }
+
+    // lock the specified lock file without waiting, creating it if needed
+    fn _lock_main(lock_file: &Path) -> Result<File, (std::io::Error, u64)> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_file)
+            .map_err(|e| (e, ERR_GENERIC))?;
+        match file.try_lock() {
+            Ok(()) => Ok(file),
+            Err(fs::TryLockError::WouldBlock) => Err((
+                std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    format!("{} is locked", lock_file.display()),
+                ),
+                ERR_LOCK_CONTENTION,
+            )),
+            Err(fs::TryLockError::Error(e)) => Err((e, ERR_GENERIC)),
+        }
+    }
+
+    let mut args = Args::parse();
+    if let Some(output_format) = args.output_format {
+        OUTPUT_FORMAT.get_or_init(|| output_format);
+        args.parsable_output = true;
+    }
+    let (verbosity, log_verbosity) = _verbosity_main(&args, None);
+
+    // progress is shown on stderr, and only if it is an interactive terminal
+    // and the output is meant for humans
+    if args.progress && !args.parsable_output && std::io::stderr().is_terminal() {
+        enable_progress();
+    }
+
+    // the validate subcommand only checks the configuration file, reporting
+    // all problems found, and exits with an error if there is any problem
+    if let Some(Command::Validate { config }) = &args.command {
+        let config_file = PathBuf::from(config)
+            .canonicalize()
+            .unwrap_or(PathBuf::from(config));
+        let config_name = config_file.to_string_lossy().to_string();
+        match validate_config(&config_file, &args.set) {
+            Ok(problems) => {
+                if problems.is_empty() {
+                    _output_main(&args, verbosity, Verbosity::Jobs, |parsable_output| {
+                        _format_message_main(
+                            parsable_output,
+                            OPERATION_VALIDATE,
+                            &config_name,
+                            None,
+                            ERR_OK,
+                            "0",
+                            &format!("configuration file {config_name} is valid"),
+                        )
+                    });
+                    return Ok(());
+                }
+                // problems are errors, and are therefore always reported
+                for problem in &problems {
+                    eprintln!(
+                        "{}",
+                        _format_problem_main(args.parsable_output, &config_name, problem)
+                    );
+                }
+                if args.parsable_output {
+                    eprintln!(
+                        "{}",
+                        format_output_parsable(
+                            CONTEXT_MAIN,
+                            &config_name,
+                            ERR_INVALID_CONFIG_FILE,
+                            OPERATION_VALIDATE,
+                            &format!("{}", problems.len()),
+                            "",
+                            json!({"problems": problems.len()}),
+                        )
+                    );
+                } else {
+                    eprintln!(
+                        "error: {} problem(s) found in configuration file {config_name}",
+                        problems.len()
+                    );
+                }
+            }
+            Err(e) => {
+                _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
                     _format_message_main(
-                        args.parsable_output,
-                        OPERATION_CONFIG,
-                        global.config_file.as_os_str().to_str().unwrap_or(""),
-                        None,
-                        "",
-                        &format!(
-                            "using configuration file {}",
-                            global.config_file.as_os_str().to_str().unwrap_or(""),
-                        ),
+                        parsable_output,
+                        OPERATION_VALIDATE,
+                        &config_name,
+                        Some(&e),
+                        ERR_INVALID_CONFIG_FILE,
+                        &format_err_parsable(ERR_INVALID_CONFIG_FILE),
+                        &format_err_verbose(ERR_INVALID_CONFIG_FILE),
                     )
-                );
+                });
             }
+        }
+        std::process::exit(EXIT_INVALID_CONFIG);
+    }
 
-            match run_jobs(&global, &jobs) {
-                Ok(_) => {
-                    if !args.quiet {
-                        println!(
-                            "{}",
-                            _format_message_main(
-                                args.parsable_output,
-                                OPERATION_MAIN_END,
-                                "",
-                                None,
-                                &format_err_parsable(ERR_OK),
-                                &format_err_verbose(ERR_OK),
-                            )
-                        );
-                    }
-                    Ok(())
-                }
-                Err(e) => {
-                    if !args.quiet {
-                        eprintln!(
-                            "{}",
-                            _format_message_main(
-                                args.parsable_output,
-                                OPERATION_MAIN_END,
-                                "",
-                                Some(e),
-                                &format_err_parsable(ERR_GENERIC),
-                                &format_err_verbose(ERR_GENERIC),
-                            )
-                        );
+    // the log file given on the command line can contain placeholders for
+    // the date and time of the run as well as environment variables, and is
+    // opened before anything else
+    if let Some(log_file) = &args.log_file {
+        let log_path = expand_path_value(
+            log_file,
+            &variables_with_timestamps(&HashMap::new(), &RUN_TIMESTAMP),
+            &environment_variables(),
+            false,
+            &home_dir().unwrap_or_default(),
+            Path::new("."),
+        )
+        .unwrap_or(log_file.clone());
+        if let Err(e) = open_log_file(
+            Path::new(&log_path),
+            args.log_max_size.unwrap_or(0),
+            log_verbosity,
+        ) {
+            _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
+                _format_message_main(
+                    parsable_output,
+                    OPERATION_LOG,
+                    &log_path,
+                    Some(&e),
+                    ERR_CANNOT_OPEN_LOG,
+                    &format_err_parsable(ERR_CANNOT_OPEN_LOG),
+                    &format_err_verbose(ERR_CANNOT_OPEN_LOG),
+                )
+            });
+            std::process::exit(EXIT_INVALID_CONFIG);
+        }
+    }
+
+    // from now on an interruption lets the files being copied be completed,
+    // and the jobs that are running end reporting the interruption
+    handle_interruptions();
+
+    // configuration files are the ones given on the command line, followed
+    // by the ones found in the scanned directory: each one is run only once,
+    // and the names are canonicalized in order to get a correct UNICODE path
+    // that includes the prefix, so that substitutions in destination file
+    // names can be performed without error; a path that does not exist is
+    // kept as is, and this will cause an error while reading configuration
+    let mut config_files: Vec<PathBuf> = Vec::new();
+    for config in args.config.iter() {
+        let config_file = PathBuf::from(config)
+            .canonicalize()
+            .unwrap_or(PathBuf::from(config));
+        if !config_files.contains(&config_file) {
+            config_files.push(config_file);
+        }
+    }
+    if let Some(scan_dir) = &args.scan {
+        match find_config_files(Path::new(scan_dir), &args.pattern) {
+            Ok((found, errors)) => {
+                for config_file in found {
+                    if !config_files.contains(&config_file) {
+                        config_files.push(config_file);
                     }
-                    std::process::exit(2);
+                }
+                // entries that cannot be read are reported, and skipped
+                for e in errors {
+                    let path = e.path().map(|p| p.to_string_lossy().to_string());
+                    let e = std::io::Error::from(e);
+                    _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
+                        _format_message_main(
+                            parsable_output,
+                            OPERATION_CONFIG,
+                            path.as_deref().unwrap_or(scan_dir),
+                            Some(&e),
+                            ERR_GENERIC,
+                            &format_err_parsable(ERR_GENERIC),
+                            &format_err_verbose(ERR_GENERIC),
+                        )
+                    });
                 }
             }
-        }
-        Err(e) => {
-            if !args.quiet {
-                eprintln!(
-                    "{}",
+            Err(e) => {
+                _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
                     _format_message_main(
-                        args.parsable_output,
+                        parsable_output,
                         OPERATION_MAIN_END,
-                        "",
-                        Some(e),
-                        &format_err_parsable(ERR_INVALID_CONFIG_FILE),
-                        &format_err_verbose(ERR_INVALID_CONFIG_FILE),
+                        scan_dir,
+                        Some(&e),
+                        ERR_GENERIC,
+                        &format_err_parsable(ERR_GENERIC),
+                        &format_err_verbose(ERR_GENERIC),
                     )
-                );
+                });
+                std::process::exit(EXIT_INVALID_CONFIG);
             }
-            std::process::exit(2);
         }
     }
+
+    // when a single configuration file is given, the first failure ends the
+    // program; otherwise failures are reported along with the configuration
+    // file they refer to, and a combined summary is written at the end
+    let combined = config_files.len() > 1 || args.scan.is_some();
+    let mut failed_configs: usize = 0;
+    let mut jobs_run: usize = 0;
+    let mut jobs_failed: usize = 0;
+    let mut files_failed: usize = 0;
+    let mut config_reports: Vec<serde_json::Value> = Vec::new();
+    let mut exit_code = EXIT_SUCCESS;
+    for config_file in config_files.iter() {
+        let (results, failure) = match _run_config_main(&args, config_file) {
+            Ok(outcome) => {
+                exit_code = exit_code.max(outcome.exit_code());
+                let code = if interrupted() {
+                    ERR_INTERRUPTED
+                } else {
+                    ERR_GENERIC
+                };
+                let failure = (outcome.halted || interrupted()).then(|| {
+                    (
+                        std::io::Error::new(
+                            std::io::ErrorKind::Interrupted,
+                            format_err_parsable(code),
+                        ),
+                        code,
+                    )
+                });
+                (outcome.results, failure)
+            }
+            Err(failure) => {
+                exit_code = exit_code.max(error_exit_code(failure.1));
+                (Vec::new(), Some(failure))
+            }
+        };
+        jobs_run += results.len();
+        jobs_failed += results
+            .iter()
+            .filter(|(_, code, _)| *code != ERR_OK)
+            .count();
+        files_failed += results
+            .iter()
+            .map(|(_, _, stats)| stats.failed_paths.len())
+            .sum::<usize>();
+        if args.report.is_some() {
+            let code = failure.as_ref().map_or(ERR_OK, |(_, code)| *code);
+            config_reports.push(json!({
+                "config_file": config_file.to_string_lossy(),
+                "result": [code, format_err_parsable(code)],
+                "error": failure.as_ref().map(|(e, _)| e.to_string()),
+                "jobs": results
+                    .iter()
+                    .map(|(name, code, stats)| stats.report(name, *code))
+                    .collect::<Vec<_>>()
+            }));
+        }
+        if let Some((e, code)) = failure {
+            failed_configs += 1;
+            _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
+                _format_message_main(
+                    parsable_output,
+                    if combined {
+                        OPERATION_CONFIG
+                    } else {
+                        OPERATION_MAIN_END
+                    },
+                    if combined {
+                        config_file.to_str().unwrap_or("")
+                    } else {
+                        ""
+                    },
+                    Some(&e),
+                    code,
+                    &format_err_parsable(code),
+                    &if combined {
+                        format!("{}: {}", config_file.display(), format_err_verbose(code))
+                    } else {
+                        format_err_verbose(code)
+                    },
+                )
+            });
+        }
+
+        // in combined runs the log file of a configuration file is only used
+        // for the messages that refer to it
+        if combined && args.log_file.is_none() {
+            close_log_file();
+        }
+
+        // the remaining configuration files are not run after an interruption
+        if interrupted() {
+            exit_code = EXIT_INTERRUPTED;
+            break;
+        }
+    }
+
+    // the result of the whole run, that matches its exit code
+    let run_code = if interrupted() {
+        ERR_INTERRUPTED
+    } else if failed_configs > 0 {
+        ERR_GENERIC
+    } else if exit_code == EXIT_JOBS_FAILED {
+        ERR_JOBS_FAILED
+    } else if exit_code == EXIT_FILES_FAILED {
+        ERR_FILES_FAILED
+    } else {
+        ERR_OK
+    };
+
+    // the report is written regardless of failures, and failing to write it
+    // is reported but does not affect the result
+    if let Some(report_file) = &args.report {
+        let report = json!({
+            "version": 1,
+            "started": format_timestamp(&RUN_TIMESTAMP, "%Y-%m-%dT%H:%M:%S%z"),
+            "duration": run_started.elapsed().as_secs_f64(),
+            "result": [run_code, format_err_parsable(run_code)],
+            "configs": {"total": config_files.len(), "failed": failed_configs},
+            "jobs": {"total": jobs_run, "failed": jobs_failed},
+            "config_files": config_reports
+        });
+        let text = serde_json::to_string_pretty(&report).unwrap_or_default();
+        if let Err(e) = fs::write(report_file, text + "\n") {
+            _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
+                _format_message_main(
+                    parsable_output,
+                    OPERATION_REPORT,
+                    report_file,
+                    Some(&e),
+                    ERR_CANNOT_WRITE_REPORT,
+                    &format_err_parsable(ERR_CANNOT_WRITE_REPORT),
+                    &format_err_verbose(ERR_CANNOT_WRITE_REPORT),
+                )
+            });
+        }
+    }
+    if !combined && failed_configs > 0 {
+        std::process::exit(exit_code);
+    }
+
+    if combined {
+        let level = if failed_configs > 0 {
+            Verbosity::Errors
+        } else {
+            Verbosity::Jobs
+        };
+        _output_main(&args, verbosity, level, |parsable_output| {
+            _format_summary_main(
+                parsable_output,
+                (config_files.len(), failed_configs),
+                (jobs_run, jobs_failed),
+            )
+        });
+    }
+
+    // jobs that failed, and files that could not be copied or removed, are
+    // reported as the result of the run even if all configuration files were
+    // run, along with the corresponding exit code
+    if run_code != ERR_OK {
+        let message = if failed_configs > 0 {
+            format!("{failed_configs} configuration file(s) failed")
+        } else if jobs_failed > 0 {
+            format!("{jobs_failed} job(s) failed")
+        } else {
+            format!("{files_failed} file(s) failed")
+        };
+        _output_main(&args, verbosity, Verbosity::Errors, |parsable_output| {
+            _format_message_main(
+                parsable_output,
+                OPERATION_MAIN_END,
+                "",
+                Some(&std::io::Error::other(message.as_str())),
+                run_code,
+                &format_err_parsable(run_code),
+                &format_err_verbose(run_code),
+            )
+        });
+        std::process::exit(exit_code);
+    }
+    _output_main(&args, verbosity, Verbosity::Jobs, |parsable_output| {
+        _format_message_main(
+            parsable_output,
+            OPERATION_MAIN_END,
+            "",
+            None,
+            ERR_OK,
+            &format_err_parsable(ERR_OK),
+            &format_err_verbose(ERR_OK),
+        )
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // build a list of owned strings
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| String::from(*item)).collect()
+    }
+
+    // create an empty directory for the files used by a test
+    fn test_directory(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("copyjob-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    // read a configuration file with no command line overrides
+    fn read_config(
+        config_file: &Path,
+    ) -> std::io::Result<(CopyJobGlobalConfig, Vec<CopyJobConfig>)> {
+        extract_config(config_file, Verbosity::Jobs, false, &[], false, &[], &[])
+    }
+
+    #[test]
+    fn dependency_order_keeps_independent_jobs_in_order() {
+        let names = strings(&["a", "b", "c"]);
+        let dependencies = vec![Vec::new(), Vec::new(), Vec::new()];
+        assert_eq!(dependency_order(&names, &dependencies), Ok(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn dependency_order_puts_dependencies_first() {
+        let names = strings(&["publish", "gather", "clean"]);
+        let dependencies = vec![strings(&["gather"]), Vec::new(), strings(&["publish"])];
+        assert_eq!(dependency_order(&names, &dependencies), Ok(vec![1, 0, 2]));
+    }
+
+    #[test]
+    fn dependency_order_ignores_unknown_jobs() {
+        let names = strings(&["a", "b"]);
+        let dependencies = vec![strings(&["x"]), strings(&["a"])];
+        assert_eq!(dependency_order(&names, &dependencies), Ok(vec![0, 1]));
+    }
+
+    #[test]
+    fn dependency_order_reports_cycles() {
+        let names = strings(&["a", "b", "c"]);
+        let dependencies = vec![Vec::new(), strings(&["c"]), strings(&["b"])];
+        assert_eq!(
+            dependency_order(&names, &dependencies),
+            Err(strings(&["b", "c", "b"]))
+        );
+    }
+
+    #[test]
+    fn dependency_order_reports_self_dependency() {
+        let names = strings(&["a"]);
+        let dependencies = vec![strings(&["a"])];
+        assert_eq!(
+            dependency_order(&names, &dependencies),
+            Err(strings(&["a", "a"]))
+        );
+    }
+
+    // build a map of template parents
+    fn parents(items: &[(&str, Option<&str>)]) -> HashMap<String, Option<String>> {
+        items
+            .iter()
+            .map(|(name, parent)| (String::from(*name), parent.map(String::from)))
+            .collect()
+    }
+
+    // resolve the templates of a configuration document
+    fn resolve(source: &str) -> Result<toml::Table, String> {
+        let mut document: toml::Table = source.parse().unwrap();
+        resolve_templates(&mut document).map(|_| document)
+    }
+
+    // get the first job of a resolved configuration document
+    fn first_job(document: &toml::Table) -> &toml::Table {
+        document["job"].as_array().unwrap()[0].as_table().unwrap()
+    }
+
+    #[test]
+    fn template_chain_follows_parents() {
+        let extends = parents(&[("a", Some("b")), ("b", Some("c")), ("c", None)]);
+        assert_eq!(template_chain("a", &extends), Ok(strings(&["a", "b", "c"])));
+        assert_eq!(template_chain("c", &extends), Ok(strings(&["c"])));
+    }
+
+    #[test]
+    fn template_chain_stops_at_undefined_templates() {
+        let extends = parents(&[("a", Some("x"))]);
+        assert_eq!(template_chain("a", &extends), Ok(strings(&["a"])));
+    }
+
+    #[test]
+    fn template_chain_reports_cycles() {
+        let extends = parents(&[("a", Some("b")), ("b", Some("c")), ("c", Some("b"))]);
+        assert_eq!(
+            template_chain("a", &extends),
+            Err(strings(&["b", "c", "b"]))
+        );
+        let extends = parents(&[("a", Some("a"))]);
+        assert_eq!(template_chain("a", &extends), Err(strings(&["a", "a"])));
+    }
+
+    #[test]
+    fn templates_are_layered() {
+        let document = resolve(
+            r#"
+            [[template]]
+            name = "base"
+            recursive = true
+            overwrite = false
+            variables = { A = "1", B = "1" }
+
+            [[template]]
+            name = "derived"
+            extends = "base"
+            overwrite = true
+            variables = { B = "2" }
+
+            [[job]]
+            name = "j"
+            extends = "derived"
+            source = "/src"
+            variables = { C = "3" }
+            "#,
+        )
+        .unwrap();
+        assert!(!document.contains_key("template"));
+        let job = first_job(&document);
+        assert_eq!(job["name"].as_str(), Some("j"));
+        assert_eq!(job["source"].as_str(), Some("/src"));
+        assert_eq!(job["recursive"].as_bool(), Some(true));
+        assert_eq!(job["overwrite"].as_bool(), Some(true));
+        assert!(!job.contains_key("extends"));
+        let variables = job["variables"].as_table().unwrap();
+        assert_eq!(variables["A"].as_str(), Some("1"));
+        assert_eq!(variables["B"].as_str(), Some("2"));
+        assert_eq!(variables["C"].as_str(), Some("3"));
+    }
+
+    #[test]
+    fn templates_concatenate_appended_patterns() {
+        let document = resolve(
+            r#"
+            [[template]]
+            name = "base"
+            patterns_exclude = ["a"]
+
+            [[template]]
+            name = "derived"
+            extends = "base"
+            patterns_exclude_append = ["b"]
+            patterns_include_append = ["x"]
+
+            [[job]]
+            name = "j"
+            extends = "derived"
+            patterns_exclude_append = ["c"]
+            "#,
+        )
+        .unwrap();
+        let job = first_job(&document);
+        let list = |key: &str| -> Vec<String> {
+            job[key]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| String::from(v.as_str().unwrap()))
+                .collect()
+        };
+        assert_eq!(list("patterns_exclude"), strings(&["a", "b", "c"]));
+        assert_eq!(list("patterns_include"), strings(&["x"]));
+        assert!(!job.contains_key("patterns_exclude_append"));
+    }
+
+    #[test]
+    fn templates_report_undefined_templates() {
+        let job_error = resolve("[[job]]\nname = \"j\"\nextends = \"missing\"\n");
+        assert_eq!(job_error, Err(String::from("job/extends:missing")));
+        let template_error = resolve("[[template]]\nname = \"t\"\nextends = \"missing\"\n");
+        assert_eq!(
+            template_error,
+            Err(String::from("template/extends:missing"))
+        );
+    }
+
+    #[test]
+    fn templates_report_cycles() {
+        let result = resolve(
+            r#"
+            [[template]]
+            name = "a"
+            extends = "b"
+
+            [[template]]
+            name = "b"
+            extends = "a"
+            "#,
+        );
+        let message = result.err().unwrap();
+        assert!(
+            message == "template/extends:a->b->a" || message == "template/extends:b->a->b",
+            "{message}"
+        );
+    }
+
+    // write a file with the given contents in a test directory
+    fn test_file(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn file_digest_matches_known_answers() {
+        let dir = test_directory("digest");
+        let empty = test_file(&dir, "empty", "");
+        let abc = test_file(&dir, "abc", "abc");
+        let cases = [
+            (
+                HashAlgorithm::Sha256,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (
+                HashAlgorithm::Xxh3,
+                "99aa06d3014798d86001c324468d497f",
+                "06b05ab6733a618578af5f94892f3950",
+            ),
+        ];
+        let mut digests: Vec<String> = Vec::new();
+        for (algorithm, empty_digest, abc_digest) in cases {
+            assert_eq!(file_digest(&empty, algorithm).unwrap(), empty_digest);
+            assert_eq!(file_digest(&abc, algorithm).unwrap(), abc_digest);
+            assert_eq!(file_digest(&abc, algorithm).unwrap(), abc_digest);
+            digests.push(String::from(abc_digest));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        digests.sort();
+        digests.dedup();
+        assert_eq!(digests.len(), 3);
+    }
+
+    #[test]
+    fn same_file_contents_compares_digests() {
+        let dir = test_directory("same-contents");
+        let original = test_file(&dir, "original", "abc");
+        let copy = test_file(&dir, "copy", "abc");
+        let changed = test_file(&dir, "changed", "abd");
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Xxh3,
+        ] {
+            assert_eq!(
+                same_file_contents(&original, &copy, algorithm, None),
+                Ok(true)
+            );
+            assert_eq!(
+                same_file_contents(&original, &changed, algorithm, None),
+                Ok(false)
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn same_file_contents_checks_sizes_first() {
+        let dir = test_directory("same-sizes");
+        let file = test_file(&dir, "file", "abc");
+        let longer = test_file(&dir, "longer", "abcd");
+        // a directory cannot be hashed, so a result means that the sizes
+        // have been compared before reading any contents
+        let subdir = dir.join("subdir");
+        create_dir_all(&subdir).unwrap();
+        let different = same_file_contents(&file, &longer, HashAlgorithm::Sha256, None);
+        let unreadable = same_file_contents(&subdir, &file, HashAlgorithm::Sha256, None);
+        let missing_source = same_file_contents(&dir.join("x"), &file, HashAlgorithm::Sha256, None);
+        let missing_destination =
+            same_file_contents(&file, &dir.join("x"), HashAlgorithm::Sha256, None);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(different, Ok(false));
+        assert_eq!(unreadable, Ok(false));
+        assert_eq!(missing_source, Err(Side::Source));
+        assert_eq!(missing_destination, Err(Side::Destination));
+    }
+
+    // build a timestamp from seconds since the epoch and an offset from UTC
+    fn timestamp(seconds: i64, hours: i8, minutes: i8) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(seconds)
+            .unwrap()
+            .to_offset(time::UtcOffset::from_hms(hours, minutes, 0).unwrap())
+    }
+
+    #[test]
+    fn format_timestamp_supports_date_and_time() {
+        // Sunday, January 3rd 2021, 10:05:09 UTC
+        let t = timestamp(1609668309, 5, 30);
+        assert_eq!(
+            format_timestamp(&t, "%Y-%m-%d %H:%M:%S %z"),
+            "2021-01-03 15:35:09 +0530"
+        );
+        assert_eq!(
+            format_timestamp(&t, "%y %a %A %b %B"),
+            "21 Sun Sunday Jan January"
+        );
+    }
+
+    #[test]
+    fn format_timestamp_supports_ordinal_and_iso_week() {
+        // the first days of 2021 belong to the last ISO week of 2020
+        let t = timestamp(1609668309, 0, 0);
+        assert_eq!(format_timestamp(&t, "%j %V %u"), "003 53 7");
+        // and the last days of 2024 to the first ISO week of 2025
+        let t = timestamp(1735516800, 0, 0);
+        assert_eq!(format_timestamp(&t, "%j %V %u"), "365 01 1");
+    }
+
+    #[test]
+    fn format_timestamp_supports_offsets() {
+        let t = timestamp(1609668309, 0, 0);
+        assert_eq!(format_timestamp(&t, "%z"), "+0000");
+        let t = timestamp(1609668309, -3, -30);
+        assert_eq!(format_timestamp(&t, "%H:%M %z"), "06:35 -0330");
+    }
+
+    #[test]
+    fn format_timestamp_keeps_unknown_specifiers() {
+        let t = timestamp(1609668309, 0, 0);
+        assert_eq!(format_timestamp(&t, "%%Y 100%%"), "%Y 100%");
+        assert_eq!(format_timestamp(&t, "%q %E%Y"), "%q %E2021");
+        assert_eq!(format_timestamp(&t, "end %"), "end %");
+    }
+
+    // build a map of variables
+    fn variables(items: &[(&str, &str)]) -> HashMap<String, String> {
+        items
+            .iter()
+            .map(|(name, value)| (String::from(*name), String::from(*value)))
+            .collect()
+    }
+
+    // replace mentions of local variables
+    fn replace_local(
+        source: &str,
+        vars: &HashMap<String, String>,
+        strict: bool,
+    ) -> Result<String, (String, String)> {
+        replace_variables_in_string(&RE_VARMENTION_LOC, source, vars, strict)
+    }
+
+    #[test]
+    fn variables_are_replaced() {
+        let vars = variables(&[("BASE", "/data"), ("NAME", "reports")]);
+        assert_eq!(
+            replace_local("%{BASE}/%{NAME}/%{BASE}", &vars, false),
+            Ok(String::from("/data/reports//data"))
+        );
+        assert_eq!(
+            replace_local("%{MISSING}/x", &vars, false),
+            Ok(String::from("/x"))
+        );
+    }
+
+    #[test]
+    fn variables_in_values_are_replaced() {
+        let vars = variables(&[("BASE", "/data"), ("DEST", "%{BASE}/backup")]);
+        assert_eq!(
+            replace_local("%{DEST}/%{DATE:-today}", &vars, false),
+            Ok(String::from("/data/backup/today"))
+        );
+    }
+
+    #[test]
+    fn variables_use_defaults_when_unset_or_empty() {
+        let vars = variables(&[("EMPTY", ""), ("SET", "value"), ("BASE", "/data")]);
+        assert_eq!(
+            replace_local("%{UNSET:-a}/%{EMPTY:-b}/%{SET:-c}", &vars, false),
+            Ok(String::from("a/b/value"))
+        );
+        assert_eq!(
+            replace_local("%{UNSET:-%{BASE}/x", &vars, false),
+            Ok(String::from("%{BASE/x"))
+        );
+        assert_eq!(
+            replace_local("%{UNSET:-}/x", &vars, true),
+            Ok(String::from("/x"))
+        );
+    }
+
+    #[test]
+    fn variables_report_errors_when_required() {
+        let vars = variables(&[("EMPTY", ""), ("SET", "value")]);
+        assert_eq!(
+            replace_local("%{SET:?missing}", &vars, false),
+            Ok(String::from("value"))
+        );
+        assert_eq!(
+            replace_local("%{EMPTY:?set EMPTY first}", &vars, false),
+            Err((String::from("EMPTY"), String::from("set EMPTY first")))
+        );
+        assert_eq!(
+            replace_local("%{UNSET:?}", &vars, false),
+            Err((String::from("UNSET"), String::from("undefined variable")))
+        );
+        assert_eq!(
+            replace_local("%{UNSET}", &vars, true),
+            Err((String::from("UNSET"), String::from("undefined variable")))
+        );
+    }
+
+    #[test]
+    fn variables_report_self_references() {
+        let vars = variables(&[("SELF", "%{SELF}/x"), ("A", "%{B}"), ("B", "a/%{A}")]);
+        assert_eq!(
+            replace_local("%{SELF}", &vars, false),
+            Err((String::from("SELF"), String::from("circular reference")))
+        );
+        assert_eq!(
+            replace_local("/%{A}", &vars, false),
+            Err((String::from("A"), String::from("circular reference")))
+        );
+    }
+
+    #[test]
+    fn include_reads_shared_files_once() {
+        let dir = test_directory("include-shared");
+        fs::write(
+            dir.join("main.toml"),
+            "active_jobs = []\ninclude = [\"@/a.toml\", \"@/b.toml\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("a.toml"), "include = [\"@/common.toml\"]\n").unwrap();
+        fs::write(dir.join("b.toml"), "include = [\"@/common.toml\"]\n").unwrap();
+        fs::write(dir.join("common.toml"), "recursive = true\n").unwrap();
+        let result = read_config(&dir.join("main.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.unwrap().0.recursive);
+    }
+
+    #[test]
+    fn include_reports_cycles() {
+        let dir = test_directory("include-cycle");
+        fs::write(
+            dir.join("main.toml"),
+            "active_jobs = []\ninclude = [\"@/a.toml\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("a.toml"), "include = [\"@/b.toml\"]\n").unwrap();
+        fs::write(dir.join("b.toml"), "include = [\"@/a.toml\"]\n").unwrap();
+        let result = read_config(&dir.join("main.toml"));
+        let (a, b) = (dir.join("a.toml"), dir.join("b.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+        let message = result.err().unwrap().to_string();
+        assert!(message.starts_with(&format_err_parsable(ERR_INVALID_CONFIG_FILE)));
+        assert!(message.ends_with(&format!(
+            "include:{}->{}->{}",
+            a.display(),
+            b.display(),
+            a.display()
+        )));
+    }
+
+    #[test]
+    fn include_reports_self_inclusion() {
+        let dir = test_directory("include-self");
+        let main = dir.join("main.toml");
+        fs::write(&main, "active_jobs = []\ninclude = [\"@/main.toml\"]\n").unwrap();
+        let result = read_config(&main);
+        fs::remove_dir_all(&dir).unwrap();
+        let message = result.err().unwrap().to_string();
+        assert!(message.ends_with(&format!("include:{}->{}", main.display(), main.display())));
+    }
+
+    #[test]
+    fn list_files_matching_applies_patterns() {
+        let dir = test_directory("list-files");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.log"), "b").unwrap();
+        let none = STR_MATCH_NO_FILE.as_str();
+        let result = list_files_matching(&dir, ".*\\.txt", none, none, false, false, true);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.unwrap(), vec![dir.join("a.txt")]);
+    }
+
+    #[test]
+    fn list_files_matching_reports_invalid_patterns() {
+        let dir = test_directory("list-files-invalid");
+        let none = STR_MATCH_NO_FILE.as_str();
+        let include = list_files_matching(&dir, "(a", none, none, false, false, true);
+        let exclude = list_files_matching(&dir, ".*", "a)", none, false, false, true);
+        let excludedir = list_files_matching(&dir, ".*", none, "[a", false, false, true);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(include.is_err());
+        assert!(exclude.is_err());
+        assert!(excludedir.is_err());
+    }
+
+    // read a job that verifies its copies from a configuration file
+    fn verifying_job(dir: &Path, atomic_copy: bool) -> CopyJobConfig {
+        let config_file = dir.join("verify.toml");
+        fs::write(
+            &config_file,
+            format!(
+                "active_jobs = [\"v\"]\n\
+                 atomic_copy = {atomic_copy}\n\
+                 verify_after_copy = true\n\
+                 [[job]]\n\
+                 name = \"v\"\n\
+                 source = \"@/\"\n\
+                 destination = \"@/\"\n"
+            ),
+        )
+        .unwrap();
+        read_config(&config_file).unwrap().1.remove(0)
+    }
+
+    // files in /proc report no size and change while being read, so copies
+    // of them never match the source when verified
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_verification_keeps_destination_written_in_place() {
+        let dir = test_directory("verify-in-place");
+        let job = verifying_job(&dir, false);
+        let source = Path::new("/proc/self/stat");
+        let destination = dir.join("stat");
+        fs::write(&destination, "previous").unwrap();
+        let outcome = write_destination_file(
+            source,
+            &destination,
+            &metadata(source).unwrap(),
+            true,
+            &job,
+            None,
+        );
+        let kept = destination.exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(outcome, Outcome::Error(FOERR_VERIFICATION_FAILED)));
+        assert!(kept);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_verification_removes_temporary_file_only() {
+        let dir = test_directory("verify-atomic");
+        let job = verifying_job(&dir, true);
+        let source = Path::new("/proc/self/stat");
+        let destination = dir.join("stat");
+        fs::write(&destination, "previous").unwrap();
+        let outcome = write_destination_file(
+            source,
+            &destination,
+            &metadata(source).unwrap(),
+            true,
+            &job,
+            None,
+        );
+        let contents = fs::read_to_string(&destination).unwrap();
+        let temporary = temporary_file_path(&destination).exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(outcome, Outcome::Error(FOERR_VERIFICATION_FAILED)));
+        assert_eq!(contents, "previous");
+        assert!(!temporary);
+    }
+
+    // build the outcome of a set of jobs from their result codes and the
+    // number of files that failed in each of them
+    fn jobs_outcome(jobs: &[(u64, usize)], halted: bool) -> JobsOutcome {
+        let results = jobs
+            .iter()
+            .map(|(code, failed)| {
+                let mut stats = JobStats {
+                    skipped_newer: 1,
+                    ..Default::default()
+                };
+                if *failed > 0 {
+                    stats.failed.insert(FOERR_GENERIC_FAILURE, *failed);
+                }
+                (String::from("job"), *code, stats)
+            })
+            .collect();
+        JobsOutcome { results, halted }
+    }
+
+    #[test]
+    fn exit_codes_follow_severity() {
+        let outcomes = [
+            (jobs_outcome(&[], false), EXIT_SUCCESS),
+            (
+                jobs_outcome(&[(ERR_OK, 0), (ERR_OK, 0)], false),
+                EXIT_SUCCESS,
+            ),
+            (
+                jobs_outcome(&[(ERR_OK, 0), (ERR_OK, 2)], false),
+                EXIT_FILES_FAILED,
+            ),
+            (
+                jobs_outcome(&[(ERR_OK, 0), (CJERR_DEPENDENCY_FAILED, 0)], false),
+                EXIT_JOBS_FAILED,
+            ),
+            (
+                jobs_outcome(&[(ERR_OK, 2), (CJERR_HALT_ON_COPY_ERROR, 1)], false),
+                EXIT_JOBS_FAILED,
+            ),
+            (jobs_outcome(&[(ERR_OK, 0)], true), EXIT_JOBS_FAILED),
+        ];
+        let errors = [
+            (ERR_INVALID_CONFIG_FILE, EXIT_INVALID_CONFIG),
+            (ERR_INVALID_PATTERN, EXIT_INVALID_CONFIG),
+            (ERR_UNDEFINED_VARIABLE, EXIT_INVALID_CONFIG),
+            (ERR_CANNOT_OPEN_LOG, EXIT_INVALID_CONFIG),
+            (ERR_LOCK_CONTENTION, EXIT_LOCK_CONTENTION),
+            (ERR_INTERRUPTED, EXIT_INTERRUPTED),
+        ];
+        let mut codes: Vec<i32> = Vec::new();
+        for (outcome, expected) in outcomes.iter() {
+            assert_eq!(outcome.exit_code(), *expected);
+            codes.push(outcome.exit_code());
+        }
+        for (code, expected) in errors {
+            assert_eq!(error_exit_code(code), expected);
+            codes.push(error_exit_code(code));
+        }
+        // the most severe problem determines the exit code of the run
+        codes.dedup();
+        assert_eq!(codes, vec![0, 1, 3, 4, 5, 6]);
+    }
 }
 
 // end.